@@ -0,0 +1,14 @@
+fn main() {
+    // regenerate the C header whenever the C API is built
+    #[cfg(feature = "capi")]
+    {
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        // into OUT_DIR, as the sources may be read-only; a test keeps include/ in step with it
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("failed to generate C bindings")
+            .write_to_file(format!("{}/vocoder_volcano.h", out_dir));
+    }
+}
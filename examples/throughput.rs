@@ -0,0 +1,60 @@
+//! Measures how throughput scales with the analysis length and with the number of streams
+//! sharing a submission. Run in release: `cargo run --release --example throughput`.
+//! With the parallel scan, a block's cost tracks the bins and streams that fill the GPU, not the
+//! block length, so audio seconds per second should grow with the streams until the GPU is busy.
+
+use std::time::Instant;
+
+use vocoder_volcano::vulcan_helper::create_vulcan_device;
+use vocoder_volcano::vocoder::{
+    StageStats, VocoderBatch, VocoderContext, VocoderSettings, BLOCK_LENGTH, MAX_ANALYSIS_LENGTH, MIN_ANALYSIS_LENGTH,
+};
+
+const WARMUP_BLOCKS: usize = 16;
+const BLOCKS: usize = 256;
+
+fn main() {
+    let (device, queues) = create_vulcan_device();
+    println!("device: {}", device.physical_device().properties().device_name);
+    let context = VocoderContext::new(device, queues);
+    let settings = VocoderSettings {
+        pitch_shift_ratio: 1.2,
+        ..VocoderSettings::default()
+    };
+    let audio_seconds = (BLOCKS * BLOCK_LENGTH) as f64 / settings.sample_rate as f64;
+
+    println!("{:>8} {:>8} {:>12} {:>12} {:>12}", "analysis", "streams", "audio s/s", "vocoder mean", "resolve mean");
+    let mut analysis_length = MIN_ANALYSIS_LENGTH;
+    while analysis_length <= MAX_ANALYSIS_LENGTH {
+        for streams in [1, 4, 16, 64] {
+            let mut batch = VocoderBatch::new(&context, &vec![settings.clone(); streams], analysis_length).unwrap();
+            batch.set_stats_enabled(true);
+            let src: Vec<Vec<f32>> = (0..streams)
+                .map(|s| (0..BLOCK_LENGTH).map(|i| ((i + s) as f32 * 0.03).sin()).collect())
+                .collect();
+            let src: Vec<&[f32]> = src.iter().map(Vec::as_slice).collect();
+            let mut dest = vec![vec![0.0f32; BLOCK_LENGTH]; streams];
+            let mut run = |blocks: usize| {
+                let mut dest: Vec<&mut [f32]> = dest.iter_mut().map(Vec::as_mut_slice).collect();
+                for _ in 0..blocks {
+                    batch.process(&src, &mut dest).unwrap();
+                }
+            };
+            run(WARMUP_BLOCKS);
+            let started = Instant::now();
+            run(BLOCKS);
+            let elapsed = started.elapsed().as_secs_f64();
+
+            let stats = batch.stats();
+            let mean = |stage: Option<StageStats>| {
+                stage.map_or_else(|| "-".to_string(), |stage| format!("{:.1?}", stage.mean))
+            };
+            println!(
+                "{:>8} {:>8} {:>12.1} {:>12} {:>12}",
+                analysis_length, streams, audio_seconds * streams as f64 / elapsed,
+                mean(stats.vocoder_dispatch), mean(stats.resolve_dispatch),
+            );
+        }
+        analysis_length *= 2;
+    }
+}
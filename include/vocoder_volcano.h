@@ -0,0 +1,150 @@
+#ifndef VOCODER_VOLCANO_H
+#define VOCODER_VOLCANO_H
+
+/* Generated by cbindgen from src/capi.rs. Do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define VV_DEVICE_NAME_LENGTH 256
+
+typedef enum VvParam {
+  VV_PARAM_PITCH_SHIFT_RATIO = 0,
+  VV_PARAM_DELAY = 1,
+  VV_PARAM_MIX_SPAN = 2,
+  VV_PARAM_EQUALIZER0 = 3,
+  VV_PARAM_EQUALIZER1 = 4,
+  VV_PARAM_EQUALIZER2 = 5,
+  VV_PARAM_EQUALIZER3 = 6,
+  VV_PARAM_EQUALIZER4 = 7,
+  VV_PARAM_EQUALIZER5 = 8,
+  VV_PARAM_EQUALIZER6 = 9,
+  VV_PARAM_EQUALIZER7 = 10,
+  VV_PARAM_FREQUENCY_SHIFT = 11,
+  // 0 for single sideband, 1 for ring modulation.
+  VV_PARAM_FREQUENCY_SHIFT_MODE = 12,
+  VV_PARAM_SAMPLE_RATE = 13,
+  // Blocks between exact Fourier state recomputations, 0 for never.
+  VV_PARAM_RESYNC_INTERVAL = 14,
+  // 0 rectangular, 1 Hann, 2 Hamming, 3 Blackman, 4 exponential.
+  VV_PARAM_ANALYSIS_WINDOW = 15,
+} VvParam;
+
+typedef enum VvStatus {
+  VV_STATUS_OK = 0,
+  VV_STATUS_NULL_POINTER = 1,
+  VV_STATUS_INVALID_ARGUMENT = 2,
+  VV_STATUS_NO_DEVICE = 3,
+  VV_STATUS_UNKNOWN_PRESET = 4,
+  // A Rust panic was caught at the boundary, usually a Vulkan failure.
+  VV_STATUS_INTERNAL = 5,
+  // The GPU was lost, e.g. to a driver reset. The output plays the dry input until
+  // `vv_vocoder_recover` moves the vocoder to a new device.
+  VV_STATUS_DEVICE_LOST = 6,
+} VvStatus;
+
+// Opaque handle to a vocoder that accepts buffers of any length.
+typedef struct VvVocoder VvVocoder;
+
+typedef struct VvDeviceInfo {
+  // NUL-terminated, truncated to fit.
+  char name[VV_DEVICE_NAME_LENGTH];
+  // `VkPhysicalDeviceType`: 1 integrated, 2 discrete, 3 virtual, 4 cpu, 0 other.
+  uint32_t device_type;
+} VvDeviceInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// A static description of a `VvStatus` value, or of an unknown one.
+const char *vv_status_string(int32_t status);
+
+// Latency added by `vv_vocoder_process`, in samples.
+uint32_t vv_latency(void);
+
+// Writes up to `capacity` entries to `dest` (which may be null when `capacity` is 0)
+// and the number of available devices to `count`. Index 0 is the preferred device.
+//
+// # Safety
+// `dest` must point to `capacity` writable entries and `count` must be writable.
+enum VvStatus vv_device_list(struct VvDeviceInfo *dest, uint32_t capacity, uint32_t *count);
+
+// Creates a vocoder with default settings on device `device_index` of `vv_device_list`,
+// or on the preferred device when `device_index` is negative. `NoDevice` if that device cannot
+// run the vocoder, `DeviceLost` if it was lost while setting up.
+//
+// # Safety
+// `out` must be writable. On success it receives a handle to release with `vv_vocoder_free`.
+enum VvStatus vv_vocoder_new(int32_t device_index, float sample_rate, struct VvVocoder **out);
+
+// # Safety
+// `vocoder` must come from `vv_vocoder_new` and not be used afterwards. Null is ignored.
+void vv_vocoder_free(struct VvVocoder *vocoder);
+
+// Processes `length` mono samples. `src` and `dest` may be the same or overlapping buffers.
+// The output lags the input by `vv_latency()` samples. `DeviceLost` still fills `dest`.
+//
+// # Safety
+// `vocoder` must be a live handle; `src` and `dest` must hold `length` samples.
+enum VvStatus vv_vocoder_process(struct VvVocoder *vocoder,
+                                 const float *src,
+                                 float *dest,
+                                 uintptr_t length);
+
+// Moves the vocoder to a new device after `vv_vocoder_process` returned `DeviceLost`, keeping
+// its settings and deadline. Blocks while the device is set up, so call it between buffers
+// from a thread other than the audio callback. `NoDevice` if there is none yet; retry later.
+//
+// # Safety
+// `vocoder` must be a live handle.
+enum VvStatus vv_vocoder_recover(struct VvVocoder *vocoder);
+
+// Clears buffered samples, e.g. after a seek.
+//
+// # Safety
+// `vocoder` must be a live handle.
+enum VvStatus vv_vocoder_reset(struct VvVocoder *vocoder);
+
+// `param` is a `VvParam`; unknown values give `InvalidArgument`.
+//
+// # Safety
+// `vocoder` must be a live handle.
+enum VvStatus vv_vocoder_set_param(struct VvVocoder *vocoder, uint32_t param, float value);
+
+// `param` is a `VvParam`; unknown values give `InvalidArgument`.
+//
+// # Safety
+// `vocoder` must be a live handle and `value` writable.
+enum VvStatus vv_vocoder_get_param(const struct VvVocoder *vocoder, uint32_t param, float *value);
+
+// Loads a built-in preset (or, with the `serde` feature, a preset file),
+// keeping the current sample rate.
+//
+// # Safety
+// `vocoder` must be a live handle and `name` a NUL-terminated string.
+enum VvStatus vv_vocoder_load_preset(struct VvVocoder *vocoder, const char *name);
+
+// Bounds how long `vv_vocoder_process` waits for the GPU per block, in milliseconds, or not at
+// all with 0. Blocks that miss it play the dry input instead.
+//
+// # Safety
+// `vocoder` must be a live handle.
+enum VvStatus vv_vocoder_set_deadline(struct VvVocoder *vocoder, float milliseconds);
+
+// Writes the deadline in milliseconds, 0 if there is none, and the number of blocks that missed
+// it so far.
+//
+// # Safety
+// `vocoder` must be a live handle; `milliseconds` and `misses` must be writable.
+enum VvStatus vv_vocoder_get_deadline(const struct VvVocoder *vocoder,
+                                      float *milliseconds,
+                                      uint64_t *misses);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* VOCODER_VOLCANO_H */
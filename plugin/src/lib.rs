@@ -0,0 +1,506 @@
+use std::{
+    env,
+    fs,
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::Duration,
+};
+
+use nih_plug::prelude::*;
+
+use vocoder_volcano::block_adapter::BlockAdapter;
+use vocoder_volcano::vulcan_helper::try_create_vulcan_device;
+use vocoder_volcano::vocoder::{
+    AnalysisWindow, FrequencyShiftMode, Vocoder, VocoderContext, VocoderSettings, BLOCK_LENGTH, DEFAULT_ANALYSIS_LENGTH,
+};
+
+
+#[derive(Enum, PartialEq, Clone, Copy)]
+pub enum ShiftMode {
+    #[name = "Single sideband"]
+    Ssb,
+    #[name = "Ring modulation"]
+    Ring,
+}
+
+impl From<FrequencyShiftMode> for ShiftMode {
+    fn from(mode: FrequencyShiftMode) -> Self {
+        match mode {
+            FrequencyShiftMode::SingleSideband => ShiftMode::Ssb,
+            FrequencyShiftMode::RingModulation => ShiftMode::Ring,
+        }
+    }
+}
+
+impl From<ShiftMode> for FrequencyShiftMode {
+    fn from(mode: ShiftMode) -> Self {
+        match mode {
+            ShiftMode::Ssb => FrequencyShiftMode::SingleSideband,
+            ShiftMode::Ring => FrequencyShiftMode::RingModulation,
+        }
+    }
+}
+
+#[derive(Enum, PartialEq, Clone, Copy)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    Exponential,
+}
+
+impl From<AnalysisWindow> for Window {
+    fn from(window: AnalysisWindow) -> Self {
+        match window {
+            AnalysisWindow::Rectangular => Window::Rectangular,
+            AnalysisWindow::Hann => Window::Hann,
+            AnalysisWindow::Hamming => Window::Hamming,
+            AnalysisWindow::Blackman => Window::Blackman,
+            AnalysisWindow::Exponential => Window::Exponential,
+        }
+    }
+}
+
+impl From<Window> for AnalysisWindow {
+    fn from(window: Window) -> Self {
+        match window {
+            Window::Rectangular => AnalysisWindow::Rectangular,
+            Window::Hann => AnalysisWindow::Hann,
+            Window::Hamming => AnalysisWindow::Hamming,
+            Window::Blackman => AnalysisWindow::Blackman,
+            Window::Exponential => AnalysisWindow::Exponential,
+        }
+    }
+}
+
+#[derive(Params)]
+pub struct VocoderParams {
+    #[id = "pitch"]
+    pub pitch_shift_ratio: FloatParam,
+    #[id = "delay"]
+    pub delay: FloatParam,
+    #[id = "mix_span"]
+    pub mix_span: FloatParam,
+    #[id = "eq0"]
+    pub eq0: FloatParam,
+    #[id = "eq1"]
+    pub eq1: FloatParam,
+    #[id = "eq2"]
+    pub eq2: FloatParam,
+    #[id = "eq3"]
+    pub eq3: FloatParam,
+    #[id = "eq4"]
+    pub eq4: FloatParam,
+    #[id = "eq5"]
+    pub eq5: FloatParam,
+    #[id = "eq6"]
+    pub eq6: FloatParam,
+    #[id = "eq7"]
+    pub eq7: FloatParam,
+    #[id = "freq_shift"]
+    pub frequency_shift: FloatParam,
+    #[id = "freq_shift_mode"]
+    pub frequency_shift_mode: EnumParam<ShiftMode>,
+    #[id = "resync"]
+    pub resync_interval: IntParam,
+    #[id = "window"]
+    pub analysis_window: EnumParam<Window>,
+    #[id = "deadline"]
+    pub deadline: FloatParam,
+}
+
+fn equalizer_param(degree: usize, default: f32) -> FloatParam {
+    FloatParam::new(
+        format!("EQ x^{}", degree),
+        default,
+        FloatRange::Linear { min: -8.0, max: 8.0 },
+    )
+    .with_step_size(0.01)
+}
+
+impl Default for VocoderParams {
+    fn default() -> Self {
+        let defaults = VocoderSettings::default();
+        Self {
+            pitch_shift_ratio: FloatParam::new(
+                "Pitch ratio",
+                defaults.pitch_shift_ratio,
+                FloatRange::Skewed { min: 0.25, max: 4.0, factor: FloatRange::skew_factor(-1.5) },
+            )
+            .with_step_size(0.001),
+            delay: FloatParam::new(
+                "Delay",
+                defaults.delay,
+                FloatRange::Linear { min: 1.0, max: (BLOCK_LENGTH - 1) as f32 },
+            )
+            .with_unit(" smp")
+            .with_step_size(1.0),
+            mix_span: FloatParam::new(
+                "Mix span",
+                defaults.mix_span,
+                FloatRange::Linear { min: 0.0, max: 1.0 },
+            ),
+            eq0: equalizer_param(0, defaults.equalizer[0]),
+            eq1: equalizer_param(1, defaults.equalizer[1]),
+            eq2: equalizer_param(2, defaults.equalizer[2]),
+            eq3: equalizer_param(3, defaults.equalizer[3]),
+            eq4: equalizer_param(4, defaults.equalizer[4]),
+            eq5: equalizer_param(5, defaults.equalizer[5]),
+            eq6: equalizer_param(6, defaults.equalizer[6]),
+            eq7: equalizer_param(7, defaults.equalizer[7]),
+            frequency_shift: FloatParam::new(
+                "Frequency shift",
+                defaults.frequency_shift,
+                FloatRange::SymmetricalSkewed {
+                    min: -2000.0, max: 2000.0, factor: FloatRange::skew_factor(-1.0), center: 0.0,
+                },
+            )
+            .with_unit(" Hz")
+            .with_step_size(0.1),
+            frequency_shift_mode: EnumParam::new("Frequency shift mode", defaults.frequency_shift_mode.into()),
+            // 0 never resyncs
+            resync_interval: IntParam::new(
+                "Resync interval",
+                defaults.resync_interval as i32,
+                IntRange::Linear { min: 0, max: 256 },
+            )
+            .with_unit(" blocks"),
+            // changing it resyncs the running state, see `Vocoder::update_settings`
+            analysis_window: EnumParam::new("Analysis window", defaults.analysis_window.into()),
+            // see `Vocoder::set_deadline`; 0 waits as long as the GPU takes
+            deadline: FloatParam::new("GPU deadline", 0.0, FloatRange::Linear { min: 0.0, max: 50.0 })
+                .with_unit(" ms")
+                .with_step_size(0.1),
+        }
+    }
+}
+
+impl VocoderParams {
+    fn deadline(&self) -> Option<Duration> {
+        let ms = self.deadline.value();
+        (ms > 0.0).then(|| Duration::from_secs_f64(ms as f64 / 1000.0))
+    }
+
+    fn settings(&self, sample_rate: f32) -> VocoderSettings {
+        VocoderSettings {
+            pitch_shift_ratio: self.pitch_shift_ratio.value(),
+            delay: self.delay.value(),
+            mix_span: self.mix_span.value(),
+            equalizer: [
+                self.eq0.value(), self.eq1.value(), self.eq2.value(), self.eq3.value(),
+                self.eq4.value(), self.eq5.value(), self.eq6.value(), self.eq7.value(),
+            ],
+            frequency_shift: self.frequency_shift.value(),
+            frequency_shift_mode: self.frequency_shift_mode.value().into(),
+            sample_rate,
+            resync_interval: self.resync_interval.value() as u32,
+            analysis_window: self.analysis_window.value().into(),
+        }
+    }
+}
+
+
+/// Where the plugin keeps compiled pipelines: `VOCODER_VOLCANO_PIPELINE_CACHE` if set, otherwise
+/// a file in the user's own cache directory, shared by every instance and host so only the first
+/// start compiles shaders. `None` if there is no such directory, which only costs compile time.
+pub fn pipeline_cache_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("VOCODER_VOLCANO_PIPELINE_CACHE").filter(|path| !path.is_empty()) {
+        return Some(PathBuf::from(path));
+    }
+    let non_empty = |name| env::var_os(name).filter(|dir| !dir.is_empty()).map(PathBuf::from);
+    let user_cache = if cfg!(windows) {
+        non_empty("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        non_empty("HOME").map(|home| home.join("Library/Caches"))
+    } else {
+        non_empty("XDG_CACHE_HOME").or_else(|| non_empty("HOME").map(|home| home.join(".cache")))
+    };
+    let dir = user_cache?.join("vocoder-volcano");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir.join("pipelines.bin"))
+}
+
+// waits between failed attempts to recover a lost device, doubled up to the last
+const FIRST_RETRY: Duration = Duration::from_millis(250);
+const LAST_RETRY: Duration = Duration::from_secs(8);
+
+pub enum Task {
+    /// Recovers the given context after a device loss, see `VocoderContext::recover`, and builds
+    /// `channels` vocoders with `settings` on it.
+    RecoverDevice { context: Arc<VocoderContext>, settings: VocoderSettings, channels: usize },
+    /// Drops what a recovery replaced, as that waits on the GPU.
+    Release(Recovered),
+}
+
+/// A context and a vocoder per channel, built off the audio thread after a device loss. Once the
+/// audio thread has swapped them in, it holds the replaced ones instead.
+pub struct Recovered {
+    context: Arc<VocoderContext>,
+    vocoders: Vec<Vocoder>,
+}
+
+pub struct VocoderPlugin {
+    params: Arc<VocoderParams>,
+    sample_rate: f32,
+    // shared by the channels, created in `initialize()`
+    context: Option<Arc<VocoderContext>>,
+    // one vocoder per channel, created in `initialize()`
+    channels: Vec<BlockAdapter<Vocoder>>,
+    // what the background task recovered, for `process_channels` to swap in
+    recovered: Arc<Mutex<Option<Recovered>>>,
+    // what `process_channels` swapped out, for the background task to drop
+    replaced: Option<Recovered>,
+    // from requesting a recovery until its vocoders are swapped in
+    recovering: bool,
+}
+
+impl VocoderPlugin {
+    /// Sets up a vocoder per channel; false if there is no GPU to run them on.
+    pub fn prepare(&mut self, channel_count: usize, sample_rate: f32) -> bool {
+        self.sample_rate = sample_rate;
+        let settings = self.params.settings(self.sample_rate);
+        let (device, queues) = match try_create_vulcan_device() {
+            Ok(device) => device,
+            Err(e) => {
+                nih_log!("vocoder unavailable: {}", e);
+                return false;
+            }
+        };
+        let vocoder_context = match pipeline_cache_path() {
+            Some(cache) => VocoderContext::with_pipeline_cache(device, queues, cache),
+            None => VocoderContext::new(device, queues),
+        };
+        let channels: Result<Vec<_>, _> = (0..channel_count)
+            .map(|_| Vocoder::try_with_context(&vocoder_context, settings.clone(), DEFAULT_ANALYSIS_LENGTH))
+            .collect();
+        match channels {
+            Ok(channels) => self.channels = channels.into_iter().map(BlockAdapter::new).collect(),
+            Err(e) => {
+                nih_log!("vocoder unavailable: {}", e);
+                return false;
+            }
+        }
+        self.context = Some(vocoder_context);
+        self.recovering = false;
+        true
+    }
+
+    /// Processes one period in place, picking up parameter changes first. After a device loss the
+    /// channels play the dry input until the vocoders the background task built are swapped in here.
+    pub fn process_channels(&mut self, channels: &mut [&mut [f32]]) {
+        if let Some(mut recovered) = self.recovered.try_lock().ok().and_then(|mut recovered| recovered.take()) {
+            // swapping only moves what is built already, and leaves the replaced vocoders and
+            // context in `recovered` to be dropped off the audio thread
+            for (channel, vocoder) in self.channels.iter_mut().zip(&mut recovered.vocoders) {
+                channel.filter_mut().take_over(vocoder);
+            }
+            if let Some(context) = &mut self.context {
+                std::mem::swap(context, &mut recovered.context);
+            }
+            self.replaced = Some(recovered);
+            self.recovering = false;
+        }
+        let settings = self.params.settings(self.sample_rate);
+        let deadline = self.params.deadline();
+        for (channel, samples) in self.channels.iter_mut().zip(channels.iter_mut()) {
+            if channel.filter().settings() != &settings {
+                channel.filter_mut().update_settings(&settings);
+            }
+            channel.filter_mut().set_deadline(deadline);
+            channel.process_in_place(samples);
+        }
+    }
+
+    /// The recovery to run off the audio thread, once per device loss.
+    pub fn recovery_request(&mut self) -> Option<Task> {
+        if self.recovering || !self.channels.iter().any(|channel| channel.filter().is_lost()) {
+            return None;
+        }
+        let context = self.context.clone()?;
+        self.recovering = true;
+        Some(Task::RecoverDevice {
+            context,
+            settings: self.params.settings(self.sample_rate),
+            channels: self.channels.len(),
+        })
+    }
+
+    /// What the last recovery replaced, to drop off the audio thread.
+    pub fn release_request(&mut self) -> Option<Task> {
+        self.replaced.take().map(Task::Release)
+    }
+
+    // Recovers `context` on a new device and builds the channels' vocoders on it, retrying with
+    // growing waits until it succeeds or the plugin is gone, and hands them over to
+    // `process_channels`. Blocks for as long as that takes.
+    fn recover(
+        context: &VocoderContext, settings: &VocoderSettings, channels: usize,
+        recovered: &Weak<Mutex<Option<Recovered>>>,
+    ) {
+        let mut retry = FIRST_RETRY;
+        loop {
+            let result = context.recover().and_then(|context| {
+                let vocoders = (0..channels)
+                    .map(|_| Vocoder::try_with_context(&context, settings.clone(), DEFAULT_ANALYSIS_LENGTH))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Recovered { context, vocoders })
+            });
+            match result {
+                Ok(built) => {
+                    if let Some(recovered) = recovered.upgrade() {
+                        *recovered.lock().unwrap() = Some(built);
+                    }
+                    return;
+                }
+                Err(e) => nih_log!("device recovery failed, retrying in {:?}: {}", retry, e),
+            }
+            thread::sleep(retry);
+            if recovered.strong_count() == 0 {
+                return;
+            }
+            retry = (retry * 2).min(LAST_RETRY);
+        }
+    }
+}
+
+impl Default for VocoderPlugin {
+    fn default() -> Self {
+        Self {
+            params: Arc::new(VocoderParams::default()),
+            sample_rate: 48000.0,
+            context: None,
+            channels: Vec::new(),
+            recovered: Arc::new(Mutex::new(None)),
+            replaced: None,
+            recovering: false,
+        }
+    }
+}
+
+impl Plugin for VocoderPlugin {
+    const NAME: &'static str = "Vocoder Volcano";
+    const VENDOR: &'static str = "hyranno";
+    const URL: &'static str = "https://github.com/hyranno/vocoder_volcano";
+    const EMAIL: &'static str = "";
+    const VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+    const AUDIO_IO_LAYOUTS: &'static [AudioIOLayout] = &[
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(2),
+            main_output_channels: NonZeroU32::new(2),
+            ..AudioIOLayout::const_default()
+        },
+        AudioIOLayout {
+            main_input_channels: NonZeroU32::new(1),
+            main_output_channels: NonZeroU32::new(1),
+            ..AudioIOLayout::const_default()
+        },
+    ];
+
+    type SysExMessage = ();
+    type BackgroundTask = Task;
+
+    fn params(&self) -> Arc<dyn Params> {
+        self.params.clone()
+    }
+
+    fn task_executor(&mut self) -> TaskExecutor<Self> {
+        let recovered = Arc::downgrade(&self.recovered);
+        Box::new(move |task| match task {
+            Task::RecoverDevice { context, settings, channels } => Self::recover(&context, &settings, channels, &recovered),
+            Task::Release(replaced) => drop(replaced),
+        })
+    }
+
+    fn initialize(
+        &mut self,
+        audio_io_layout: &AudioIOLayout,
+        buffer_config: &BufferConfig,
+        context: &mut impl InitContext<Self>,
+    ) -> bool {
+        let channel_count = audio_io_layout.main_output_channels.map(NonZeroU32::get).unwrap_or(0) as usize;
+        if !self.prepare(channel_count, buffer_config.sample_rate) {
+            return false;
+        }
+        context.set_latency_samples(BlockAdapter::<Vocoder>::LATENCY as u32);
+        true
+    }
+
+    fn reset(&mut self) {
+        self.channels.iter_mut().for_each(|channel| channel.reset());
+    }
+
+    fn process(
+        &mut self,
+        buffer: &mut Buffer,
+        _aux: &mut AuxiliaryBuffers,
+        context: &mut impl ProcessContext<Self>,
+    ) -> ProcessStatus {
+        self.process_channels(buffer.as_slice());
+        for task in [self.recovery_request(), self.release_request()].into_iter().flatten() {
+            context.execute_background(task);
+        }
+        ProcessStatus::Normal
+    }
+}
+
+impl ClapPlugin for VocoderPlugin {
+    const CLAP_ID: &'static str = "com.github.hyranno.vocoder-volcano";
+    const CLAP_DESCRIPTION: Option<&'static str> = Some("GPU phase vocoder pitch and frequency shifter");
+    const CLAP_MANUAL_URL: Option<&'static str> = Some(Self::URL);
+    const CLAP_SUPPORT_URL: Option<&'static str> = None;
+    const CLAP_FEATURES: &'static [ClapFeature] = &[
+        ClapFeature::AudioEffect,
+        ClapFeature::PitchShifter,
+        ClapFeature::Stereo,
+        ClapFeature::Mono,
+    ];
+}
+
+impl Vst3Plugin for VocoderPlugin {
+    const VST3_CLASS_ID: [u8; 16] = *b"VocoderVolcanoFX";
+    const VST3_SUBCATEGORIES: &'static [Vst3SubCategory] = &[
+        Vst3SubCategory::Fx,
+        Vst3SubCategory::PitchShift,
+    ];
+}
+
+nih_export_clap!(VocoderPlugin);
+nih_export_vst3!(VocoderPlugin);
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the standalone's dummy backend does, minus running until interrupted:
+    // periods of silence-padded input through `initialize` and `process`'s code path.
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn runs_headless() {
+        let mut plugin = VocoderPlugin::default();
+        assert!(plugin.prepare(2, 48000.0));
+        let period = 512;
+        let periods = 64;
+        let mut output = Vec::new();
+        for p in 0..periods {
+            let mut left: Vec<f32> = (0..period).map(|i| ((p * period + i) as f32 * 0.05).sin() * 0.5).collect();
+            let mut right = left.clone();
+            plugin.process_channels(&mut [&mut left[..], &mut right[..]]);
+            assert_eq!(left, right);
+            output.extend(left);
+        }
+        assert!(output.iter().all(|s| s.is_finite()));
+        assert!(output[..BlockAdapter::<Vocoder>::LATENCY].iter().all(|&s| s == 0.0));
+        assert!(output[BlockAdapter::<Vocoder>::LATENCY..].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn defaults_follow_the_library() {
+        let params = VocoderParams::default();
+        assert_eq!(params.settings(VocoderSettings::default().sample_rate), VocoderSettings::default());
+    }
+}
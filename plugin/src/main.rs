@@ -0,0 +1,8 @@
+use nih_plug::prelude::*;
+
+use vocoder_volcano_plugin::VocoderPlugin;
+
+// `vocoder_volcano_standalone --backend dummy` runs the plugin without any audio device.
+fn main() {
+    nih_export_standalone::<VocoderPlugin>();
+}
@@ -0,0 +1,91 @@
+use std::{
+    error::Error,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+
+use vocoder_volcano::cli::{DeadlineArgs, SettingsArgs};
+use vocoder_volcano::jack_client::JackVocoder;
+use vocoder_volcano::vulcan_helper::{create_vulcan_device};
+use vocoder_volcano::vocoder::{Vocoder, VocoderSettings, BLOCK_LENGTH};
+
+
+/// Realtime voice changer as a JACK client with ports `in` and `out`.
+#[derive(Parser)]
+#[command(name = "vocoder-volcano-jack", version)]
+struct Args {
+    /// JACK client name
+    #[arg(long, default_value = "vocoder-volcano")]
+    name: String,
+    /// Port to connect to our input, e.g. system:capture_1 (repeatable)
+    #[arg(long)]
+    connect_input: Vec<String>,
+    /// Port to connect our output to, e.g. system:playback_1 (repeatable)
+    #[arg(long)]
+    connect_output: Vec<String>,
+    /// Samples of delay between input and output, to absorb GPU jitter; reported to JACK as latency
+    #[arg(long, default_value_t = 2 * BLOCK_LENGTH)]
+    prefill: usize,
+    #[command(flatten)]
+    deadline: DeadlineArgs,
+    /// Stop after this many seconds instead of running until killed
+    #[arg(long)]
+    duration: Option<f64>,
+    #[command(flatten)]
+    settings: SettingsArgs,
+}
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if args.prefill < BLOCK_LENGTH {
+        return Err(format!("--prefill must be at least {}", BLOCK_LENGTH).into());
+    }
+    let deadline = args.deadline.deadline;
+    // the sample rate is only known once connected to the server
+    let settings = args.settings.settings(0)?;
+
+    let vocoder = JackVocoder::new(
+        &args.name,
+        move |sample_rate| {
+            let (_, mut queues) = create_vulcan_device();
+            let settings = VocoderSettings {
+                sample_rate: sample_rate as f32,
+                ..settings
+            };
+            let mut vocoder = Vocoder::new(queues.next().unwrap(), settings);
+            vocoder.set_deadline(deadline);
+            vocoder
+        },
+        args.prefill,
+    )?;
+    let client = vocoder.client();
+    for port in args.connect_input.iter() {
+        client.connect_ports_by_name(port, vocoder.input_port_name())?;
+    }
+    for port in args.connect_output.iter() {
+        client.connect_ports_by_name(vocoder.output_port_name(), port)?;
+    }
+    let sample_rate = vocoder.sample_rate();
+    eprintln!(
+        "{} -> {} at {} Hz, latency {} samples",
+        vocoder.input_port_name(), vocoder.output_port_name(), sample_rate, vocoder.latency(),
+    );
+
+    let start = Instant::now();
+    while args.duration.map(|d| start.elapsed().as_secs_f64() < d).unwrap_or(true) {
+        thread::sleep(Duration::from_secs(1));
+        let stats = vocoder.stats();
+        eprintln!(
+            "worst block {:.2} ms, overruns {} in / {} out, underruns {}, blocks {}",
+            stats.worst_process_time.as_secs_f64() * 1000.0,
+            stats.input_overruns,
+            stats.output_overruns,
+            stats.output_underruns,
+            stats.blocks,
+        );
+    }
+    Ok(())
+}
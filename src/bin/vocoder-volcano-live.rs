@@ -0,0 +1,177 @@
+use std::{
+    error::Error,
+    thread,
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{BufferSize, Device, Sample, SampleFormat, Stream, StreamConfig};
+
+use vocoder_volcano::cli::{DeadlineArgs, SettingsArgs};
+use vocoder_volcano::realtime::{Duplex, InputEnd, OutputEnd};
+use vocoder_volcano::vulcan_helper::{create_vulcan_device};
+use vocoder_volcano::vocoder::{Vocoder, BLOCK_LENGTH};
+
+// largest callback handled in one go; bigger callbacks are split
+const SCRATCH_LENGTH: usize = 8192;
+
+
+/// Realtime voice changer from an input device to an output device.
+#[derive(Parser)]
+#[command(name = "vocoder-volcano-live", version)]
+struct Args {
+    /// Print the available input and output devices and exit
+    #[arg(long)]
+    list_devices: bool,
+    /// Input device name (default device if omitted)
+    #[arg(long)]
+    input_device: Option<String>,
+    /// Output device name (default device if omitted)
+    #[arg(long)]
+    output_device: Option<String>,
+    /// Device buffer size in frames (device default if omitted)
+    #[arg(long)]
+    buffer_size: Option<u32>,
+    /// Extra silence queued for playback, in samples, to absorb GPU jitter
+    #[arg(long, default_value_t = BLOCK_LENGTH)]
+    prefill: usize,
+    #[command(flatten)]
+    deadline: DeadlineArgs,
+    /// Stop after this many seconds instead of running until killed
+    #[arg(long)]
+    duration: Option<f64>,
+    #[command(flatten)]
+    settings: SettingsArgs,
+}
+
+
+fn find_device(
+    devices: impl Iterator<Item = Device>, name: &Option<String>, default: Option<Device>,
+) -> Result<Device, Box<dyn Error>> {
+    match name {
+        Some(name) => devices
+            .into_iter()
+            .find(|d| d.name().map(|n| &n == name).unwrap_or(false))
+            .ok_or_else(|| format!("no device named \"{}\"", name).into()),
+        None => default.ok_or_else(|| "no default device".into()),
+    }
+}
+
+fn build_input<T: Sample>(device: &Device, config: &StreamConfig, mut input: InputEnd) -> Result<Stream, Box<dyn Error>> {
+    let channels = config.channels as usize;
+    let mut scratch = vec![0.0f32; SCRATCH_LENGTH];
+    let stream = device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            // the vocoder is mono: keep the first channel
+            for chunk in data.chunks(SCRATCH_LENGTH * channels) {
+                let frames = chunk.len() / channels;
+                for (s, frame) in scratch.iter_mut().zip(chunk.chunks(channels)) {
+                    *s = frame[0].to_f32();
+                }
+                input.push(&scratch[..frames]);
+            }
+        },
+        |e| eprintln!("input stream error: {}", e),
+    )?;
+    Ok(stream)
+}
+
+fn build_output<T: Sample>(device: &Device, config: &StreamConfig, mut output: OutputEnd) -> Result<Stream, Box<dyn Error>> {
+    let channels = config.channels as usize;
+    let mut scratch = vec![0.0f32; SCRATCH_LENGTH];
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for chunk in data.chunks_mut(SCRATCH_LENGTH * channels) {
+                let frames = chunk.len() / channels;
+                output.pull(&mut scratch[..frames]);
+                for (s, frame) in scratch.iter().zip(chunk.chunks_mut(channels)) {
+                    frame.iter_mut().for_each(|d| *d = T::from(s));
+                }
+            }
+        },
+        |e| eprintln!("output stream error: {}", e),
+    )?;
+    Ok(stream)
+}
+
+fn stream_config(device: &Device, input: bool, buffer_size: Option<u32>) -> Result<(StreamConfig, SampleFormat), Box<dyn Error>> {
+    let supported = if input { device.default_input_config()? } else { device.default_output_config()? };
+    let mut config = supported.config();
+    if let Some(frames) = buffer_size {
+        config.buffer_size = BufferSize::Fixed(frames);
+    }
+    Ok((config, supported.sample_format()))
+}
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    let host = cpal::default_host();
+    if args.list_devices {
+        println!("input devices:");
+        for device in host.input_devices()? {
+            println!("  {}", device.name()?);
+        }
+        println!("output devices:");
+        for device in host.output_devices()? {
+            println!("  {}", device.name()?);
+        }
+        return Ok(());
+    }
+
+    let input_device = find_device(host.input_devices()?, &args.input_device, host.default_input_device())?;
+    let output_device = find_device(host.output_devices()?, &args.output_device, host.default_output_device())?;
+    let (input_config, input_format) = stream_config(&input_device, true, args.buffer_size)?;
+    let (mut output_config, output_format) = stream_config(&output_device, false, args.buffer_size)?;
+    // no resampling: run both ends at the capture rate
+    output_config.sample_rate = input_config.sample_rate;
+    let sample_rate = input_config.sample_rate.0;
+    let deadline = args.deadline.deadline;
+    let settings = args.settings.settings(sample_rate)?;
+
+    let (duplex, input, output) = Duplex::new(
+        move || {
+            let (_, mut queues) = create_vulcan_device();
+            let mut vocoder = Vocoder::new(queues.next().unwrap(), settings);
+            vocoder.set_deadline(deadline);
+            vocoder
+        },
+        4 * BLOCK_LENGTH + 2 * SCRATCH_LENGTH,
+        args.prefill,
+    );
+
+    let input_stream = match input_format {
+        SampleFormat::F32 => build_input::<f32>(&input_device, &input_config, input)?,
+        SampleFormat::I16 => build_input::<i16>(&input_device, &input_config, input)?,
+        SampleFormat::U16 => build_input::<u16>(&input_device, &input_config, input)?,
+    };
+    let output_stream = match output_format {
+        SampleFormat::F32 => build_output::<f32>(&output_device, &output_config, output)?,
+        SampleFormat::I16 => build_output::<i16>(&output_device, &output_config, output)?,
+        SampleFormat::U16 => build_output::<u16>(&output_device, &output_config, output)?,
+    };
+    input_stream.play()?;
+    output_stream.play()?;
+    eprintln!(
+        "{} -> {} at {} Hz",
+        input_device.name()?, output_device.name()?, sample_rate,
+    );
+
+    let start = Instant::now();
+    while args.duration.map(|d| start.elapsed().as_secs_f64() < d).unwrap_or(true) {
+        thread::sleep(Duration::from_secs(1));
+        let stats = duplex.stats();
+        eprintln!(
+            "latency {:.1} ms, worst block {:.2} ms, overruns {} in / {} out, underruns {}",
+            stats.latency(sample_rate).as_secs_f64() * 1000.0,
+            stats.worst_process_time.as_secs_f64() * 1000.0,
+            stats.input_overruns,
+            stats.output_overruns,
+            stats.output_underruns,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,141 @@
+use std::{
+    error::Error,
+    path::PathBuf,
+};
+
+use clap::Parser;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+
+use vocoder_volcano::cli::SettingsArgs;
+use vocoder_volcano::preset::BUILTIN_PRESETS;
+use vocoder_volcano::vulcan_helper::{create_vulcan_device};
+use vocoder_volcano::vocoder::{
+    Vocoder, VocoderContext, VocoderError, DEFAULT_ANALYSIS_LENGTH, MAX_ANALYSIS_LENGTH, MIN_ANALYSIS_LENGTH,
+};
+
+
+/// Offline pitch and frequency shifting of WAV files.
+#[derive(Parser)]
+#[command(name = "vocoder-volcano", version)]
+struct Args {
+    /// Input WAV file
+    #[arg(required_unless_present = "list_presets")]
+    input: Option<PathBuf>,
+    /// Output WAV file, written with the input's channel count, rate and bit depth, and as long as
+    /// the input plus the delay line, so the shifted tail rings out
+    #[arg(required_unless_present = "list_presets")]
+    output: Option<PathBuf>,
+
+    /// Print the built-in preset names and exit
+    #[arg(long)]
+    list_presets: bool,
+    #[command(flatten)]
+    settings: SettingsArgs,
+    /// Frequency bins of the analysis, a power of two from 256 to 4096; more resolves lower voices
+    #[arg(long, default_value_t = DEFAULT_ANALYSIS_LENGTH)]
+    analysis_length: usize,
+    /// Keep compiled GPU pipelines in this file, so later runs start faster
+    #[arg(long)]
+    pipeline_cache: Option<PathBuf>,
+
+    /// Scale the whole output down instead of clamping when it would clip
+    #[arg(long)]
+    normalize: bool,
+}
+
+
+fn read_samples(reader: WavReader<std::io::BufReader<std::fs::File>>) -> Result<Vec<f32>, hound::Error> {
+    let spec = reader.spec();
+    match spec.sample_format {
+        SampleFormat::Float => reader.into_samples::<f32>().collect(),
+        SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader.into_samples::<i32>().map(|s| s.map(|s| s as f32 / scale)).collect()
+        }
+    }
+}
+
+fn write_samples(path: &PathBuf, spec: WavSpec, samples: &[f32]) -> Result<(), hound::Error> {
+    let mut writer = WavWriter::create(path, spec)?;
+    match spec.sample_format {
+        SampleFormat::Float => {
+            for &sample in samples {
+                writer.write_sample(sample)?;
+            }
+        }
+        SampleFormat::Int => {
+            let scale = ((1i64 << (spec.bits_per_sample - 1)) - 1) as f32;
+            for &sample in samples {
+                writer.write_sample((sample * scale).round() as i32)?;
+            }
+        }
+    }
+    writer.finalize()
+}
+
+// Runs each interleaved channel through its vocoder, followed by `tail` frames of silence.
+fn process_channels(vocoders: &mut [Vocoder], samples: &[f32], tail: usize) -> Result<Vec<f32>, VocoderError> {
+    let channels = vocoders.len();
+    let mut output = vec![0.0f32; samples.len() + tail * channels];
+    for (channel, vocoder) in vocoders.iter_mut().enumerate() {
+        let mut src: Vec<f32> = samples.iter().skip(channel).step_by(channels).copied().collect();
+        src.resize(src.len() + tail, 0.0);
+        for (o, d) in output.iter_mut().skip(channel).step_by(channels).zip(vocoder.render(&src)?) {
+            *o = d;
+        }
+    }
+    Ok(output)
+}
+
+/// Keeps samples within [-1, 1], either by clamping or by scaling everything down.
+fn protect_clipping(samples: &mut [f32], normalize: bool) {
+    let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+    if peak <= 1.0 {
+        return;
+    }
+    if normalize {
+        samples.iter_mut().for_each(|s| *s /= peak);
+        eprintln!("normalized output by {:.2} dB to avoid clipping", -20.0 * peak.log10());
+    } else {
+        let clipped = samples.iter().filter(|s| s.abs() > 1.0).count();
+        samples.iter_mut().for_each(|s| *s = s.clamp(-1.0, 1.0));
+        eprintln!("clamped {} samples (peak {:.2}); use --normalize to avoid clipping", clipped, peak);
+    }
+}
+
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if args.list_presets {
+        BUILTIN_PRESETS.iter().for_each(|name| println!("{}", name));
+        return Ok(());
+    }
+    let (input, output) = (args.input.clone().unwrap(), args.output.clone().unwrap());
+    let analysis_length = args.analysis_length;
+    if !analysis_length.is_power_of_two() || !(MIN_ANALYSIS_LENGTH..=MAX_ANALYSIS_LENGTH).contains(&analysis_length) {
+        return Err(format!(
+            "--analysis-length must be a power of two from {} to {}", MIN_ANALYSIS_LENGTH, MAX_ANALYSIS_LENGTH,
+        ).into());
+    }
+
+    let reader = WavReader::open(&input)?;
+    let spec = reader.spec();
+    let settings = args.settings.settings(spec.sample_rate)?;
+    let samples = read_samples(reader)?;
+
+    let (device, queues) = create_vulcan_device();
+    let context = match &args.pipeline_cache {
+        Some(path) => VocoderContext::with_pipeline_cache(device, queues, path),
+        None => VocoderContext::new(device, queues),
+    };
+    let mut vocoders: Vec<Vocoder> = (0..spec.channels)
+        .map(|_| Vocoder::with_context(&context, settings.clone(), analysis_length))
+        .collect();
+
+    // the pitch shift reads up to a whole delay line behind the input
+    let tail = settings.delay.ceil() as usize + 1;
+    let mut transformed = process_channels(&mut vocoders, &samples, tail)?;
+    protect_clipping(&mut transformed, args.normalize);
+    write_samples(&output, spec, &transformed)?;
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use crate::vocoder::{AudioFilter, BLOCK_LENGTH};
+
+
+/// Lets a block-based `AudioFilter` run on buffers of any length,
+/// at a fixed latency of `BLOCK_LENGTH` samples.
+pub struct BlockAdapter<F: AudioFilter> {
+    filter: F,
+    input: [f32; BLOCK_LENGTH],
+    output: [f32; BLOCK_LENGTH],
+    position: usize,
+}
+
+impl<F: AudioFilter> BlockAdapter<F> {
+    pub const LATENCY: usize = BLOCK_LENGTH;
+
+    pub fn new(filter: F) -> BlockAdapter<F> {
+        BlockAdapter {
+            filter,
+            input: [0.0; BLOCK_LENGTH],
+            output: [0.0; BLOCK_LENGTH],
+            position: 0,
+        }
+    }
+
+    pub fn filter(&self) -> &F {
+        &self.filter
+    }
+    pub fn filter_mut(&mut self) -> &mut F {
+        &mut self.filter
+    }
+
+    /// Processes `samples` in place.
+    pub fn process_in_place(&mut self, samples: &mut [f32]) {
+        for sample in samples.iter_mut() {
+            self.input[self.position] = *sample;
+            *sample = self.output[self.position];
+            self.position += 1;
+            if self.position == BLOCK_LENGTH {
+                self.filter.process(&self.input, &mut self.output);
+                self.position = 0;
+            }
+        }
+    }
+
+    /// Clears the buffered samples. The filter's own state is left alone.
+    pub fn reset(&mut self) {
+        self.input = [0.0; BLOCK_LENGTH];
+        self.output = [0.0; BLOCK_LENGTH];
+        self.position = 0;
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Passthrough;
+    impl AudioFilter for Passthrough {
+        fn process(&mut self, src: &[f32], dest: &mut [f32]) {
+            dest.copy_from_slice(src);
+        }
+    }
+
+    #[test]
+    fn delays_by_block_length() {
+        let mut adapter = BlockAdapter::new(Passthrough);
+        let source: Vec<f32> = (0..5000).map(|i| i as f32).collect();
+        let mut samples = source.clone();
+        for chunk in samples.chunks_mut(300) {
+            adapter.process_in_place(chunk);
+        }
+        assert!(samples[..BLOCK_LENGTH].iter().all(|&s| s == 0.0));
+        assert_eq!(&samples[BLOCK_LENGTH..], &source[..source.len() - BLOCK_LENGTH]);
+    }
+}
@@ -0,0 +1,636 @@
+//! C API for the cdylib. `include/vocoder_volcano.h` is generated from this file by cbindgen;
+//! the build writes a fresh copy to `OUT_DIR`, and `header_is_up_to_date` fails until it is copied over.
+
+use std::{
+    ffi::{c_char, CStr},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+    time::Duration,
+};
+
+use vulkano::device::DeviceCreationError;
+
+use crate::block_adapter::BlockAdapter;
+use crate::preset::Preset;
+use crate::vulcan_helper::{try_create_vulcan_device_on, try_create_vulcan_instance, enumerate_compute_devices};
+use crate::vocoder::{
+    AnalysisWindow, FrequencyShiftMode, Vocoder, VocoderContext, VocoderError, VocoderSettings, DEFAULT_ANALYSIS_LENGTH,
+};
+
+
+pub const VV_DEVICE_NAME_LENGTH: usize = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VvStatus {
+    Ok = 0,
+    NullPointer = 1,
+    InvalidArgument = 2,
+    NoDevice = 3,
+    UnknownPreset = 4,
+    /// A Rust panic was caught at the boundary, usually a Vulkan failure.
+    Internal = 5,
+    /// The GPU was lost, e.g. to a driver reset. The output plays the dry input until
+    /// `vv_vocoder_recover` moves the vocoder to a new device.
+    DeviceLost = 6,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VvParam {
+    PitchShiftRatio = 0,
+    Delay = 1,
+    MixSpan = 2,
+    Equalizer0 = 3,
+    Equalizer1 = 4,
+    Equalizer2 = 5,
+    Equalizer3 = 6,
+    Equalizer4 = 7,
+    Equalizer5 = 8,
+    Equalizer6 = 9,
+    Equalizer7 = 10,
+    FrequencyShift = 11,
+    /// 0 for single sideband, 1 for ring modulation.
+    FrequencyShiftMode = 12,
+    SampleRate = 13,
+    /// Blocks between exact Fourier state recomputations, 0 for never.
+    ResyncInterval = 14,
+    /// 0 rectangular, 1 Hann, 2 Hamming, 3 Blackman, 4 exponential.
+    AnalysisWindow = 15,
+}
+
+// C passes the enums as plain integers, which may hold any value, so they are checked on the way in
+impl TryFrom<i32> for VvStatus {
+    type Error = VvStatus;
+
+    fn try_from(value: i32) -> Result<VvStatus, VvStatus> {
+        Ok(match value {
+            0 => VvStatus::Ok,
+            1 => VvStatus::NullPointer,
+            2 => VvStatus::InvalidArgument,
+            3 => VvStatus::NoDevice,
+            4 => VvStatus::UnknownPreset,
+            5 => VvStatus::Internal,
+            6 => VvStatus::DeviceLost,
+            _ => return Err(VvStatus::InvalidArgument),
+        })
+    }
+}
+
+impl TryFrom<u32> for VvParam {
+    type Error = VvStatus;
+
+    fn try_from(value: u32) -> Result<VvParam, VvStatus> {
+        Ok(match value {
+            0 => VvParam::PitchShiftRatio,
+            1 => VvParam::Delay,
+            2 => VvParam::MixSpan,
+            3 => VvParam::Equalizer0,
+            4 => VvParam::Equalizer1,
+            5 => VvParam::Equalizer2,
+            6 => VvParam::Equalizer3,
+            7 => VvParam::Equalizer4,
+            8 => VvParam::Equalizer5,
+            9 => VvParam::Equalizer6,
+            10 => VvParam::Equalizer7,
+            11 => VvParam::FrequencyShift,
+            12 => VvParam::FrequencyShiftMode,
+            13 => VvParam::SampleRate,
+            14 => VvParam::ResyncInterval,
+            15 => VvParam::AnalysisWindow,
+            _ => return Err(VvStatus::InvalidArgument),
+        })
+    }
+}
+
+#[repr(C)]
+pub struct VvDeviceInfo {
+    /// NUL-terminated, truncated to fit.
+    pub name: [c_char; VV_DEVICE_NAME_LENGTH],
+    /// `VkPhysicalDeviceType`: 1 integrated, 2 discrete, 3 virtual, 4 cpu, 0 other.
+    pub device_type: u32,
+}
+
+/// Opaque handle to a vocoder that accepts buffers of any length.
+pub struct VvVocoder {
+    inner: BlockAdapter<Vocoder>,
+}
+
+
+fn guard(f: impl FnOnce() -> VvStatus) -> VvStatus {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(VvStatus::Internal)
+}
+
+fn param_slot(settings: &mut VocoderSettings, param: VvParam) -> Option<&mut f32> {
+    match param {
+        VvParam::PitchShiftRatio => Some(&mut settings.pitch_shift_ratio),
+        VvParam::Delay => Some(&mut settings.delay),
+        VvParam::MixSpan => Some(&mut settings.mix_span),
+        VvParam::Equalizer0 => Some(&mut settings.equalizer[0]),
+        VvParam::Equalizer1 => Some(&mut settings.equalizer[1]),
+        VvParam::Equalizer2 => Some(&mut settings.equalizer[2]),
+        VvParam::Equalizer3 => Some(&mut settings.equalizer[3]),
+        VvParam::Equalizer4 => Some(&mut settings.equalizer[4]),
+        VvParam::Equalizer5 => Some(&mut settings.equalizer[5]),
+        VvParam::Equalizer6 => Some(&mut settings.equalizer[6]),
+        VvParam::Equalizer7 => Some(&mut settings.equalizer[7]),
+        VvParam::FrequencyShift => Some(&mut settings.frequency_shift),
+        VvParam::SampleRate => Some(&mut settings.sample_rate),
+        VvParam::FrequencyShiftMode | VvParam::ResyncInterval | VvParam::AnalysisWindow => None,
+    }
+}
+
+fn set_param(settings: &mut VocoderSettings, param: VvParam, value: f32) -> VvStatus {
+    if !value.is_finite() {
+        return VvStatus::InvalidArgument;
+    }
+    let mut updated = settings.clone();
+    match param_slot(&mut updated, param) {
+        Some(slot) => *slot = value,
+        None => {
+            if !set_discrete_param(&mut updated, param, value) {
+                return VvStatus::InvalidArgument;
+            }
+        }
+    }
+    if updated.sample_rate <= 0.0 {
+        return VvStatus::InvalidArgument;
+    }
+    *settings = updated;
+    VvStatus::Ok
+}
+
+fn get_param(settings: &VocoderSettings, param: VvParam) -> f32 {
+    let mut settings = settings.clone();
+    match param_slot(&mut settings, param) {
+        Some(slot) => *slot,
+        None => get_discrete_param(&settings, param),
+    }
+}
+
+// `name`'s settings at the current sample rate
+fn preset_settings(settings: &VocoderSettings, name: &str) -> Result<VocoderSettings, VvStatus> {
+    #[cfg(feature = "serde")]
+    let preset = Preset::load_by_name(name);
+    #[cfg(not(feature = "serde"))]
+    let preset = Preset::builtin(name);
+    match preset {
+        Ok(preset) => Ok(VocoderSettings {
+            sample_rate: settings.sample_rate,
+            ..preset.settings
+        }),
+        Err(_) => Err(VvStatus::UnknownPreset),
+    }
+}
+
+// the parameters `param_slot` leaves out, passed as whole numbers; false if `value` is out of range
+fn set_discrete_param(settings: &mut VocoderSettings, param: VvParam, value: f32) -> bool {
+    if value < 0.0 {
+        return false;
+    }
+    let index = value as u32;
+    match param {
+        VvParam::FrequencyShiftMode => {
+            settings.frequency_shift_mode = match index {
+                0 => FrequencyShiftMode::SingleSideband,
+                1 => FrequencyShiftMode::RingModulation,
+                _ => return false,
+            };
+        }
+        VvParam::ResyncInterval => settings.resync_interval = index,
+        VvParam::AnalysisWindow => {
+            settings.analysis_window = match index {
+                0 => AnalysisWindow::Rectangular,
+                1 => AnalysisWindow::Hann,
+                2 => AnalysisWindow::Hamming,
+                3 => AnalysisWindow::Blackman,
+                4 => AnalysisWindow::Exponential,
+                _ => return false,
+            };
+        }
+        _ => return false,
+    }
+    true
+}
+
+fn get_discrete_param(settings: &VocoderSettings, param: VvParam) -> f32 {
+    match param {
+        VvParam::FrequencyShiftMode => match settings.frequency_shift_mode {
+            FrequencyShiftMode::SingleSideband => 0.0,
+            FrequencyShiftMode::RingModulation => 1.0,
+        },
+        VvParam::ResyncInterval => settings.resync_interval as f32,
+        VvParam::AnalysisWindow => match settings.analysis_window {
+            AnalysisWindow::Rectangular => 0.0,
+            AnalysisWindow::Hann => 1.0,
+            AnalysisWindow::Hamming => 2.0,
+            AnalysisWindow::Blackman => 3.0,
+            AnalysisWindow::Exponential => 4.0,
+        },
+        _ => 0.0,
+    }
+}
+
+
+/// A static description of a `VvStatus` value, or of an unknown one.
+#[no_mangle]
+pub extern "C" fn vv_status_string(status: i32) -> *const c_char {
+    let message: &'static [u8] = match VvStatus::try_from(status) {
+        Err(_) => b"unknown status\0",
+        Ok(VvStatus::Ok) => b"ok\0",
+        Ok(VvStatus::NullPointer) => b"null pointer\0",
+        Ok(VvStatus::InvalidArgument) => b"invalid argument\0",
+        Ok(VvStatus::NoDevice) => b"no suitable Vulkan device\0",
+        Ok(VvStatus::UnknownPreset) => b"unknown preset\0",
+        Ok(VvStatus::Internal) => b"internal error\0",
+        Ok(VvStatus::DeviceLost) => b"the Vulkan device was lost\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Latency added by `vv_vocoder_process`, in samples.
+#[no_mangle]
+pub extern "C" fn vv_latency() -> u32 {
+    BlockAdapter::<Vocoder>::LATENCY as u32
+}
+
+/// Writes up to `capacity` entries to `dest` (which may be null when `capacity` is 0)
+/// and the number of available devices to `count`. Index 0 is the preferred device.
+///
+/// # Safety
+/// `dest` must point to `capacity` writable entries and `count` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn vv_device_list(dest: *mut VvDeviceInfo, capacity: u32, count: *mut u32) -> VvStatus {
+    if count.is_null() || (dest.is_null() && capacity > 0) {
+        return VvStatus::NullPointer;
+    }
+    guard(|| {
+        let instance = match try_create_vulcan_instance() {
+            Ok(instance) => instance,
+            Err(_) => return VvStatus::NoDevice,
+        };
+        let devices = enumerate_compute_devices(&instance);
+        for (i, (device, _)) in devices.iter().take(capacity as usize).enumerate() {
+            let properties = device.properties();
+            let info = &mut *dest.add(i);
+            info.name = [0; VV_DEVICE_NAME_LENGTH];
+            for (d, s) in info.name.iter_mut().zip(properties.device_name.bytes().take(VV_DEVICE_NAME_LENGTH - 1)) {
+                *d = s as c_char;
+            }
+            info.device_type = properties.device_type as u32;
+        }
+        *count = devices.len() as u32;
+        VvStatus::Ok
+    })
+}
+
+/// Creates a vocoder with default settings on device `device_index` of `vv_device_list`,
+/// or on the preferred device when `device_index` is negative. `NoDevice` if that device cannot
+/// run the vocoder, `DeviceLost` if it was lost while setting up.
+///
+/// # Safety
+/// `out` must be writable. On success it receives a handle to release with `vv_vocoder_free`.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_new(device_index: i32, sample_rate: f32, out: *mut *mut VvVocoder) -> VvStatus {
+    if out.is_null() {
+        return VvStatus::NullPointer;
+    }
+    *out = ptr::null_mut();
+    if sample_rate.is_nan() || sample_rate <= 0.0 {
+        return VvStatus::InvalidArgument;
+    }
+    guard(|| {
+        let instance = match try_create_vulcan_instance() {
+            Ok(instance) => instance,
+            Err(_) => return VvStatus::NoDevice,
+        };
+        let mut devices = enumerate_compute_devices(&instance);
+        let index = if device_index < 0 { 0 } else { device_index as usize };
+        if index >= devices.len() {
+            return VvStatus::NoDevice;
+        }
+        let (physical_device, queue_family_index) = devices.swap_remove(index);
+        let (device, queues) = match try_create_vulcan_device_on(physical_device, queue_family_index) {
+            Ok(created) => created,
+            Err(DeviceCreationError::DeviceLost) => return VvStatus::DeviceLost,
+            Err(_) => return VvStatus::NoDevice,
+        };
+        let settings = VocoderSettings {
+            sample_rate,
+            ..VocoderSettings::default()
+        };
+        let context = VocoderContext::new(device, queues);
+        match Vocoder::try_with_context(&context, settings, DEFAULT_ANALYSIS_LENGTH) {
+            Ok(vocoder) => {
+                *out = Box::into_raw(Box::new(VvVocoder { inner: BlockAdapter::new(vocoder) }));
+                VvStatus::Ok
+            }
+            Err(VocoderError::DeviceLost) => VvStatus::DeviceLost,
+            Err(_) => VvStatus::NoDevice,
+        }
+    })
+}
+
+/// # Safety
+/// `vocoder` must come from `vv_vocoder_new` and not be used afterwards. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_free(vocoder: *mut VvVocoder) {
+    if !vocoder.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(vocoder))));
+    }
+}
+
+/// Processes `length` mono samples. `src` and `dest` may be the same or overlapping buffers.
+/// The output lags the input by `vv_latency()` samples. `DeviceLost` still fills `dest`.
+///
+/// # Safety
+/// `vocoder` must be a live handle; `src` and `dest` must hold `length` samples.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_process(
+    vocoder: *mut VvVocoder, src: *const f32, dest: *mut f32, length: usize,
+) -> VvStatus {
+    if vocoder.is_null() || src.is_null() || dest.is_null() {
+        return VvStatus::NullPointer;
+    }
+    guard(|| {
+        ptr::copy(src, dest, length);
+        let samples = std::slice::from_raw_parts_mut(dest, length);
+        (*vocoder).inner.process_in_place(samples);
+        match (*vocoder).inner.filter().is_lost() {
+            true => VvStatus::DeviceLost,
+            false => VvStatus::Ok,
+        }
+    })
+}
+
+/// Moves the vocoder to a new device after `vv_vocoder_process` returned `DeviceLost`, keeping
+/// its settings and deadline. Blocks while the device is set up, so call it between buffers
+/// from a thread other than the audio callback. `NoDevice` if there is none yet; retry later.
+///
+/// # Safety
+/// `vocoder` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_recover(vocoder: *mut VvVocoder) -> VvStatus {
+    if vocoder.is_null() {
+        return VvStatus::NullPointer;
+    }
+    guard(|| match (*vocoder).inner.filter_mut().recover() {
+        Ok(()) => VvStatus::Ok,
+        Err(VocoderError::DeviceLost) => VvStatus::DeviceLost,
+        Err(_) => VvStatus::NoDevice,
+    })
+}
+
+/// Clears buffered samples, e.g. after a seek.
+///
+/// # Safety
+/// `vocoder` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_reset(vocoder: *mut VvVocoder) -> VvStatus {
+    if vocoder.is_null() {
+        return VvStatus::NullPointer;
+    }
+    guard(|| {
+        (*vocoder).inner.reset();
+        VvStatus::Ok
+    })
+}
+
+/// `param` is a `VvParam`; unknown values give `InvalidArgument`.
+///
+/// # Safety
+/// `vocoder` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_set_param(vocoder: *mut VvVocoder, param: u32, value: f32) -> VvStatus {
+    if vocoder.is_null() {
+        return VvStatus::NullPointer;
+    }
+    let param = match VvParam::try_from(param) {
+        Ok(param) => param,
+        Err(status) => return status,
+    };
+    guard(|| {
+        let filter = (*vocoder).inner.filter_mut();
+        let mut settings = filter.settings().clone();
+        let status = set_param(&mut settings, param, value);
+        if status == VvStatus::Ok {
+            filter.update_settings(&settings);
+        }
+        status
+    })
+}
+
+/// `param` is a `VvParam`; unknown values give `InvalidArgument`.
+///
+/// # Safety
+/// `vocoder` must be a live handle and `value` writable.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_get_param(vocoder: *const VvVocoder, param: u32, value: *mut f32) -> VvStatus {
+    if vocoder.is_null() || value.is_null() {
+        return VvStatus::NullPointer;
+    }
+    let param = match VvParam::try_from(param) {
+        Ok(param) => param,
+        Err(status) => return status,
+    };
+    guard(|| {
+        *value = get_param((*vocoder).inner.filter().settings(), param);
+        VvStatus::Ok
+    })
+}
+
+/// Loads a built-in preset (or, with the `serde` feature, a preset file),
+/// keeping the current sample rate.
+///
+/// # Safety
+/// `vocoder` must be a live handle and `name` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_load_preset(vocoder: *mut VvVocoder, name: *const c_char) -> VvStatus {
+    if vocoder.is_null() || name.is_null() {
+        return VvStatus::NullPointer;
+    }
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(name) => name,
+        Err(_) => return VvStatus::InvalidArgument,
+    };
+    guard(|| {
+        let filter = (*vocoder).inner.filter_mut();
+        match preset_settings(filter.settings(), name) {
+            Ok(settings) => {
+                filter.update_settings(&settings);
+                VvStatus::Ok
+            }
+            Err(status) => status,
+        }
+    })
+}
+
+/// Bounds how long `vv_vocoder_process` waits for the GPU per block, in milliseconds, or not at
+/// all with 0. Blocks that miss it play the dry input instead.
+///
+/// # Safety
+/// `vocoder` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_set_deadline(vocoder: *mut VvVocoder, milliseconds: f32) -> VvStatus {
+    if vocoder.is_null() {
+        return VvStatus::NullPointer;
+    }
+    let deadline = match deadline_from_ms(milliseconds) {
+        Ok(deadline) => deadline,
+        Err(status) => return status,
+    };
+    guard(|| {
+        (*vocoder).inner.filter_mut().set_deadline(deadline);
+        VvStatus::Ok
+    })
+}
+
+/// Writes the deadline in milliseconds, 0 if there is none, and the number of blocks that missed
+/// it so far.
+///
+/// # Safety
+/// `vocoder` must be a live handle; `milliseconds` and `misses` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn vv_vocoder_get_deadline(
+    vocoder: *const VvVocoder, milliseconds: *mut f32, misses: *mut u64,
+) -> VvStatus {
+    if vocoder.is_null() || milliseconds.is_null() || misses.is_null() {
+        return VvStatus::NullPointer;
+    }
+    guard(|| {
+        let filter = (*vocoder).inner.filter();
+        *milliseconds = filter.deadline().map_or(0.0, |deadline| deadline.as_secs_f32() * 1000.0);
+        *misses = filter.deadline_misses();
+        VvStatus::Ok
+    })
+}
+
+fn deadline_from_ms(milliseconds: f32) -> Result<Option<Duration>, VvStatus> {
+    if milliseconds == 0.0 {
+        Ok(None)
+    } else if milliseconds > 0.0 && milliseconds.is_finite() {
+        Ok(Some(Duration::from_secs_f64(milliseconds as f64 / 1000.0)))
+    } else {
+        Err(VvStatus::InvalidArgument)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn null_handles_are_rejected() {
+        unsafe {
+            assert_eq!(vv_vocoder_new(-1, 48000.0, ptr::null_mut()), VvStatus::NullPointer);
+            assert_eq!(vv_vocoder_process(ptr::null_mut(), ptr::null(), ptr::null_mut(), 0), VvStatus::NullPointer);
+            assert_eq!(vv_vocoder_set_param(ptr::null_mut(), VvParam::Delay as u32, 1.0), VvStatus::NullPointer);
+            assert_eq!(vv_vocoder_recover(ptr::null_mut()), VvStatus::NullPointer);
+            assert_eq!(vv_device_list(ptr::null_mut(), 1, ptr::null_mut()), VvStatus::NullPointer);
+            vv_vocoder_free(ptr::null_mut());
+
+            let mut out = ptr::dangling_mut::<VvVocoder>();
+            assert_eq!(vv_vocoder_new(-1, 0.0, &mut out), VvStatus::InvalidArgument);
+            assert!(out.is_null());
+            assert_eq!(CStr::from_ptr(vv_status_string(VvStatus::Ok as i32)).to_str().unwrap(), "ok");
+        }
+    }
+
+    #[test]
+    fn header_is_up_to_date() {
+        let generated = include_str!(concat!(env!("OUT_DIR"), "/vocoder_volcano.h"));
+        let checked_in = include_str!("../include/vocoder_volcano.h");
+        assert!(generated == checked_in, "include/vocoder_volcano.h is stale; copy it over from {}", env!("OUT_DIR"));
+    }
+
+    const ALL_PARAMS: [VvParam; 16] = [
+        VvParam::PitchShiftRatio, VvParam::Delay, VvParam::MixSpan,
+        VvParam::Equalizer0, VvParam::Equalizer1, VvParam::Equalizer2, VvParam::Equalizer3,
+        VvParam::Equalizer4, VvParam::Equalizer5, VvParam::Equalizer6, VvParam::Equalizer7,
+        VvParam::FrequencyShift, VvParam::FrequencyShiftMode, VvParam::SampleRate,
+        VvParam::ResyncInterval, VvParam::AnalysisWindow,
+    ];
+
+    #[test]
+    fn params_round_trip() {
+        let mut settings = VocoderSettings::default();
+        for (i, param) in ALL_PARAMS.into_iter().enumerate() {
+            // valid for every parameter, discrete ones included
+            let value = 1.0 + (i % 2) as f32;
+            assert_eq!(set_param(&mut settings, param, value), VvStatus::Ok, "{:?}", param);
+            assert_eq!(get_param(&settings, param), value, "{:?}", param);
+        }
+        let before = settings.clone();
+        assert_eq!(set_param(&mut settings, VvParam::AnalysisWindow, 5.0), VvStatus::InvalidArgument);
+        assert_eq!(set_param(&mut settings, VvParam::ResyncInterval, -1.0), VvStatus::InvalidArgument);
+        assert_eq!(set_param(&mut settings, VvParam::SampleRate, 0.0), VvStatus::InvalidArgument);
+        assert_eq!(set_param(&mut settings, VvParam::Delay, f32::NAN), VvStatus::InvalidArgument);
+        assert_eq!(settings, before);
+    }
+
+    #[test]
+    fn unknown_enum_values_are_rejected() {
+        for param in ALL_PARAMS {
+            assert_eq!(VvParam::try_from(param as u32), Ok(param));
+        }
+        assert_eq!(VvParam::try_from(ALL_PARAMS.len() as u32), Err(VvStatus::InvalidArgument));
+        for status in 0..=6 {
+            assert_eq!(VvStatus::try_from(status).map(|status| status as i32), Ok(status));
+        }
+        assert_eq!(VvStatus::try_from(7), Err(VvStatus::InvalidArgument));
+        unsafe {
+            assert_eq!(CStr::from_ptr(vv_status_string(-1)).to_str().unwrap(), "unknown status");
+        }
+    }
+
+    #[test]
+    fn deadline_is_checked() {
+        assert_eq!(deadline_from_ms(0.0), Ok(None));
+        assert_eq!(deadline_from_ms(2.5), Ok(Some(Duration::from_micros(2500))));
+        for ms in [-1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(deadline_from_ms(ms), Err(VvStatus::InvalidArgument));
+        }
+    }
+
+    #[test]
+    fn presets_keep_the_sample_rate() {
+        let settings = VocoderSettings { sample_rate: 44100.0, ..VocoderSettings::default() };
+        let radio = preset_settings(&settings, "radio").unwrap();
+        assert_eq!(radio.sample_rate, 44100.0);
+        assert_eq!(radio.equalizer, Preset::builtin("radio").unwrap().settings.equalizer);
+        assert_eq!(preset_settings(&settings, "no such preset"), Err(VvStatus::UnknownPreset));
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn vocoder_round_trip() {
+        unsafe {
+            let mut vocoder = ptr::null_mut();
+            assert_eq!(vv_vocoder_new(-1, 48000.0, &mut vocoder), VvStatus::Ok);
+            assert_eq!(vv_vocoder_set_param(vocoder, VvParam::PitchShiftRatio as u32, 1.5), VvStatus::Ok);
+            let mut value = 0.0;
+            assert_eq!(vv_vocoder_get_param(vocoder, VvParam::PitchShiftRatio as u32, &mut value), VvStatus::Ok);
+            assert_eq!(value, 1.5);
+            assert_eq!(vv_vocoder_load_preset(vocoder, c"radio".as_ptr()), VvStatus::Ok);
+            assert_eq!(vv_vocoder_get_param(vocoder, VvParam::Equalizer1 as u32, &mut value), VvStatus::Ok);
+            assert_eq!(value, 6.0);
+            assert_eq!(vv_vocoder_load_preset(vocoder, c"no such preset".as_ptr()), VvStatus::UnknownPreset);
+            assert_eq!(vv_vocoder_set_deadline(vocoder, 50.0), VvStatus::Ok);
+            let mut misses = u64::MAX;
+            assert_eq!(vv_vocoder_get_deadline(vocoder, &mut value, &mut misses), VvStatus::Ok);
+            assert_eq!((value, misses), (50.0, 0));
+
+            // in place, then shifted by a few samples within one buffer
+            let length = 4 * vv_latency() as usize;
+            let mut samples: Vec<f32> = (0..length + 3).map(|i| (i as f32 * 0.05).sin()).collect();
+            assert_eq!(vv_vocoder_process(vocoder, samples.as_ptr(), samples.as_mut_ptr(), length), VvStatus::Ok);
+            assert!(samples[..vv_latency() as usize].iter().all(|&s| s == 0.0));
+            assert!(samples[vv_latency() as usize..length].iter().any(|&s| s != 0.0));
+            let src = samples.as_ptr();
+            assert_eq!(vv_vocoder_process(vocoder, src, samples.as_mut_ptr().add(3), length), VvStatus::Ok);
+            assert!(samples.iter().all(|s| s.is_finite()));
+            vv_vocoder_free(vocoder);
+        }
+    }
+}
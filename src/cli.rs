@@ -0,0 +1,118 @@
+use std::time::Duration;
+
+use clap::{Args, ValueEnum};
+
+use crate::preset::{Preset, PresetError};
+use crate::vocoder::{AnalysisWindow, FrequencyShiftMode, VocoderSettings};
+
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ShiftMode {
+    Ssb,
+    Ring,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    Exponential,
+}
+
+/// Command-line flags for every `VocoderSettings` field, shared by the binaries.
+#[derive(Args)]
+pub struct SettingsArgs {
+    /// Built-in preset name or path to a .json/.toml preset file
+    #[arg(long)]
+    pub preset: Option<String>,
+
+    #[arg(long)]
+    pub pitch_shift_ratio: Option<f32>,
+    #[arg(long)]
+    pub delay: Option<f32>,
+    #[arg(long)]
+    pub mix_span: Option<f32>,
+    /// Equalizer polynomial coefficients, lowest degree first (up to 8)
+    #[arg(long, value_delimiter = ',', num_args = 1..=8, allow_negative_numbers = true)]
+    pub equalizer: Option<Vec<f32>>,
+    /// Linear frequency shift in Hz
+    #[arg(long, allow_negative_numbers = true)]
+    pub frequency_shift: Option<f32>,
+    #[arg(long, value_enum)]
+    pub frequency_shift_mode: Option<ShiftMode>,
+    /// Blocks between exact recomputations of the Fourier state (0 never)
+    #[arg(long)]
+    pub resync_interval: Option<u32>,
+    /// Analysis window of the sliding DFT
+    #[arg(long, value_enum)]
+    pub analysis_window: Option<Window>,
+}
+
+impl SettingsArgs {
+    /// Starts from the preset (or the defaults) and applies every flag given on top.
+    pub fn settings(&self, sample_rate: u32) -> Result<VocoderSettings, PresetError> {
+        let mut settings = match &self.preset {
+            Some(name) => Preset::load_by_name(name)?.settings,
+            None => VocoderSettings::default(),
+        };
+        settings.sample_rate = sample_rate as f32;
+        if let Some(v) = self.pitch_shift_ratio { settings.pitch_shift_ratio = v; }
+        if let Some(v) = self.delay { settings.delay = v; }
+        if let Some(v) = self.mix_span { settings.mix_span = v; }
+        if let Some(coefs) = &self.equalizer {
+            settings.equalizer = [0.0; 8];
+            settings.equalizer[..coefs.len()].copy_from_slice(coefs);
+        }
+        if let Some(v) = self.frequency_shift { settings.frequency_shift = v; }
+        if let Some(mode) = self.frequency_shift_mode {
+            settings.frequency_shift_mode = match mode {
+                ShiftMode::Ssb => FrequencyShiftMode::SingleSideband,
+                ShiftMode::Ring => FrequencyShiftMode::RingModulation,
+            };
+        }
+        if let Some(v) = self.resync_interval { settings.resync_interval = v; }
+        if let Some(window) = self.analysis_window {
+            settings.analysis_window = match window {
+                Window::Rectangular => AnalysisWindow::Rectangular,
+                Window::Hann => AnalysisWindow::Hann,
+                Window::Hamming => AnalysisWindow::Hamming,
+                Window::Blackman => AnalysisWindow::Blackman,
+                Window::Exponential => AnalysisWindow::Exponential,
+            };
+        }
+        Ok(settings)
+    }
+}
+
+/// The `--deadline-ms` flag of the realtime binaries, see `Vocoder::set_deadline`.
+#[derive(Args)]
+pub struct DeadlineArgs {
+    /// Longest wait for the GPU per block, in milliseconds; late blocks play the dry input instead
+    #[arg(long = "deadline-ms", value_name = "MS", value_parser = parse_deadline)]
+    pub deadline: Option<Duration>,
+}
+
+fn parse_deadline(src: &str) -> Result<Duration, String> {
+    let ms: f64 = src.parse().map_err(|e| format!("{}", e))?;
+    // also rejects NaN
+    if !(ms > 0.0 && ms.is_finite()) {
+        return Err("must be a positive number of milliseconds".into());
+    }
+    Ok(Duration::from_secs_f64(ms / 1000.0))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deadline_must_be_positive() {
+        assert_eq!(parse_deadline("2.5"), Ok(Duration::from_micros(2500)));
+        for src in ["0", "-1", "NaN", "inf", "soon"] {
+            assert!(parse_deadline(src).is_err(), "{}", src);
+        }
+    }
+}
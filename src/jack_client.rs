@@ -0,0 +1,214 @@
+//! JACK client that runs an `AudioFilter` through a `realtime::Duplex`.
+
+use std::{
+    ffi::c_void,
+};
+
+use jack::{
+    jack_sys, AsyncClient, AudioIn, AudioOut, Client, ClientOptions, Control, Frames, Port, ProcessHandler, ProcessScope,
+};
+
+use crate::realtime::{Duplex, InputEnd, OutputEnd, StatsSnapshot};
+use crate::vocoder::{AudioFilter, BLOCK_LENGTH};
+
+
+struct Process {
+    input_port: Port<AudioIn>,
+    output_port: Port<AudioOut>,
+    input: InputEnd,
+    output: OutputEnd,
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, _: &Client, ps: &ProcessScope) -> Control {
+        self.input.push(self.input_port.as_slice(ps));
+        self.output.pull(self.output_port.as_mut_slice(ps));
+        Control::Continue
+    }
+}
+
+
+// read by the latency callback on JACK's notification thread
+struct LatencyReport {
+    input_port: *mut jack_sys::jack_port_t,
+    output_port: *mut jack_sys::jack_port_t,
+    latency: Frames,
+}
+
+unsafe extern "C" fn latency_callback(mode: jack_sys::jack_latency_callback_mode_t, arg: *mut c_void) {
+    let report = &*(arg as *const LatencyReport);
+    // capture latency flows from our input to our output, playback latency the other way
+    let (from, to) = if mode == jack_sys::JackCaptureLatency {
+        (report.input_port, report.output_port)
+    } else {
+        (report.output_port, report.input_port)
+    };
+    let mut range = jack_sys::jack_latency_range_t { min: 0, max: 0 };
+    jack_sys::jack_port_get_latency_range(from, mode, &mut range);
+    let mut range = jack_sys::jack_latency_range_t {
+        min: range.min + report.latency,
+        max: range.max + report.latency,
+    };
+    jack_sys::jack_port_set_latency_range(to, mode, &mut range);
+}
+
+
+/// A JACK client with one mono input port `in` and one output port `out`.
+/// The process callback only copies samples; the filter runs on the `Duplex` worker.
+pub struct JackVocoder {
+    // dropped first, so the callbacks stop before what they point to goes away
+    client: AsyncClient<(), Process>,
+    duplex: Duplex,
+    _latency_report: Box<LatencyReport>,
+    input_port_name: String,
+    output_port_name: String,
+    latency: Frames,
+}
+
+impl JackVocoder {
+    /// Connects to a running server as `name` and activates the client.
+    /// `make_filter` receives the server's sample rate and runs on the worker thread.
+    /// The client reports `prefill` samples of latency, which is the delay from `in` to `out`
+    /// as long as the worker keeps up; it should be at least `BLOCK_LENGTH`.
+    pub fn new<F, M>(name: &str, make_filter: M, prefill: usize) -> Result<JackVocoder, jack::Error>
+    where
+        F: AudioFilter,
+        M: FnOnce(u32) -> F + Send + 'static,
+    {
+        let (client, _) = Client::new(name, ClientOptions::NO_START_SERVER)?;
+        let sample_rate = client.sample_rate() as u32;
+        let input_port = client.register_port("in", AudioIn)?;
+        let output_port = client.register_port("out", AudioOut)?;
+        let input_port_name = input_port.name()?;
+        let output_port_name = output_port.name()?;
+
+        let latency = prefill as Frames;
+        let mut latency_report = Box::new(LatencyReport {
+            input_port: input_port.raw(),
+            output_port: output_port.raw(),
+            latency,
+        });
+        let registered = unsafe {
+            jack_sys::jack_set_latency_callback(
+                client.raw(),
+                Some(latency_callback),
+                latency_report.as_mut() as *mut LatencyReport as *mut c_void,
+            )
+        };
+        if registered != 0 {
+            return Err(jack::Error::CallbackRegistrationError);
+        }
+
+        let capacity = 4 * BLOCK_LENGTH + 2 * client.buffer_size() as usize;
+        let (duplex, input, output) = Duplex::new(move || make_filter(sample_rate), capacity, prefill);
+        let process = Process { input_port, output_port, input, output };
+        let client = client.activate_async((), process)?;
+        Ok(JackVocoder {
+            client,
+            duplex,
+            _latency_report: latency_report,
+            input_port_name,
+            output_port_name,
+            latency,
+        })
+    }
+
+    pub fn client(&self) -> &Client {
+        self.client.as_client()
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.client().sample_rate() as u32
+    }
+    /// Latency reported to the server, in samples.
+    pub fn latency(&self) -> Frames {
+        self.latency
+    }
+    pub fn input_port_name(&self) -> &str {
+        &self.input_port_name
+    }
+    pub fn output_port_name(&self) -> &str {
+        &self.output_port_name
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        self.duplex.stats()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicU32, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+    use jack::{ClosureProcessHandler, LatencyType};
+
+    struct Passthrough;
+    impl AudioFilter for Passthrough {
+        fn process(&mut self, src: &[f32], dest: &mut [f32]) {
+            dest.copy_from_slice(src);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a running JACK server, e.g. `jackd -d dummy`"]
+    fn dummy_server_round_trip() {
+        let prefill = 2 * BLOCK_LENGTH;
+        let vocoder = JackVocoder::new("vocoder-volcano-test", |_| Passthrough, prefill).unwrap();
+
+        let armed = Arc::new(AtomicBool::new(false));
+        let emitted = Arc::new(AtomicU32::new(0));
+        let detected = Arc::new(AtomicU32::new(u32::MAX));
+
+        let (source, _) = Client::new("vocoder-volcano-test-source", ClientOptions::NO_START_SERVER).unwrap();
+        let mut source_port = source.register_port("out", AudioOut).unwrap();
+        let source_port_name = source_port.name().unwrap();
+        let source = {
+            let armed = armed.clone();
+            let emitted = emitted.clone();
+            source.activate_async((), ClosureProcessHandler::new(move |_: &Client, ps: &ProcessScope| {
+                let out = source_port.as_mut_slice(ps);
+                out.fill(0.0);
+                if armed.swap(false, Ordering::AcqRel) {
+                    out[0] = 1.0;
+                    emitted.store(ps.last_frame_time(), Ordering::Release);
+                }
+                Control::Continue
+            })).unwrap()
+        };
+
+        let (sink, _) = Client::new("vocoder-volcano-test-sink", ClientOptions::NO_START_SERVER).unwrap();
+        let sink_port = sink.register_port("in", AudioIn).unwrap();
+        let sink_port_name = sink_port.name().unwrap();
+        let sink = {
+            let detected = detected.clone();
+            sink.activate_async((), ClosureProcessHandler::new(move |_: &Client, ps: &ProcessScope| {
+                if let Some(i) = sink_port.as_slice(ps).iter().position(|&s| s > 0.5) {
+                    let time = ps.last_frame_time().wrapping_add(i as u32);
+                    detected.compare_exchange(u32::MAX, time, Ordering::AcqRel, Ordering::Acquire).ok();
+                }
+                Control::Continue
+            })).unwrap()
+        };
+
+        let client = source.as_client();
+        client.connect_ports_by_name(&source_port_name, vocoder.input_port_name()).unwrap();
+        client.connect_ports_by_name(vocoder.output_port_name(), &sink_port_name).unwrap();
+        thread::sleep(Duration::from_millis(200));
+        armed.store(true, Ordering::Release);
+        thread::sleep(Duration::from_secs(1));
+
+        let delay = detected.load(Ordering::Acquire).wrapping_sub(emitted.load(Ordering::Acquire));
+        assert_eq!(vocoder.stats().output_underruns, 0);
+        assert_eq!(delay as usize, prefill);
+        let output_port = client.port_by_name(vocoder.output_port_name()).unwrap();
+        assert_eq!(output_port.get_latency_range(LatencyType::Capture), (prefill as Frames, prefill as Frames));
+        drop((source, sink));
+    }
+}
@@ -1,6 +1,25 @@
 pub mod vocoder;
+pub mod preset;
+pub mod block_adapter;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "realtime")]
+pub mod realtime;
+#[cfg(feature = "jack")]
+pub mod jack_client;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "python")]
+mod python;
 pub mod vulcan_helper;
 
+// the integration tests' fixtures, for the unit tests to share; they name the crate as those do
+#[cfg(test)]
+extern crate self as vocoder_volcano;
+#[cfg(test)]
+#[path = "../tests/common/mod.rs"]
+mod test_common;
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
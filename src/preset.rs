@@ -0,0 +1,181 @@
+use std::fmt;
+
+use crate::vocoder::{FrequencyShiftMode, VocoderSettings};
+
+
+/// Version written to preset files. Files with a newer version are rejected.
+pub const PRESET_VERSION: u32 = 1;
+
+pub const BUILTIN_PRESETS: [&str; 5] = ["neutral", "chipmunk", "deep", "robot", "radio"];
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Preset {
+    pub version: u32,
+    pub name: String,
+    pub settings: VocoderSettings,
+}
+
+#[derive(Debug)]
+pub enum PresetError {
+    UnknownPreset(String),
+    UnsupportedVersion(u32),
+    UnknownFormat(String),
+    Io(std::io::Error),
+    #[cfg(feature = "serde")]
+    Json(serde_json::Error),
+    #[cfg(feature = "serde")]
+    TomlDe(toml::de::Error),
+    #[cfg(feature = "serde")]
+    TomlSer(toml::ser::Error),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PresetError::UnknownPreset(name) => write!(f, "unknown preset \"{}\"", name),
+            PresetError::UnsupportedVersion(version) => write!(
+                f, "preset version {} is newer than supported version {}", version, PRESET_VERSION
+            ),
+            PresetError::UnknownFormat(path) => write!(f, "cannot tell preset format of \"{}\"", path),
+            PresetError::Io(e) => write!(f, "{}", e),
+            #[cfg(feature = "serde")]
+            PresetError::Json(e) => write!(f, "{}", e),
+            #[cfg(feature = "serde")]
+            PresetError::TomlDe(e) => write!(f, "{}", e),
+            #[cfg(feature = "serde")]
+            PresetError::TomlSer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+impl From<std::io::Error> for PresetError {
+    fn from(e: std::io::Error) -> Self {
+        PresetError::Io(e)
+    }
+}
+
+
+impl Preset {
+    pub fn new(name: &str, settings: VocoderSettings) -> Preset {
+        Preset {
+            version: PRESET_VERSION,
+            name: name.to_string(),
+            settings,
+        }
+    }
+
+    pub fn builtin(name: &str) -> Result<Preset, PresetError> {
+        let defaults = VocoderSettings::default();
+        let settings = match name {
+            "neutral" => defaults,
+            "chipmunk" => VocoderSettings {
+                pitch_shift_ratio: 1.6,
+                ..defaults
+            },
+            "deep" => VocoderSettings {
+                pitch_shift_ratio: 0.75,
+                equalizer: [1.2, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                ..defaults
+            },
+            "robot" => VocoderSettings {
+                frequency_shift: 70.0,
+                frequency_shift_mode: FrequencyShiftMode::RingModulation,
+                ..defaults
+            },
+            "radio" => VocoderSettings {
+                equalizer: [0.0, 6.0, -6.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+                ..defaults
+            },
+            _ => return Err(PresetError::UnknownPreset(name.to_string())),
+        };
+        Ok(Preset::new(name, settings))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Preset {
+    pub fn from_json_str(src: &str) -> Result<Preset, PresetError> {
+        serde_json::from_str::<Preset>(src).map_err(PresetError::Json)?.check_version()
+    }
+    pub fn to_json_string(&self) -> Result<String, PresetError> {
+        serde_json::to_string_pretty(self).map_err(PresetError::Json)
+    }
+    pub fn from_toml_str(src: &str) -> Result<Preset, PresetError> {
+        toml::from_str::<Preset>(src).map_err(PresetError::TomlDe)?.check_version()
+    }
+    pub fn to_toml_string(&self) -> Result<String, PresetError> {
+        toml::to_string_pretty(self).map_err(PresetError::TomlSer)
+    }
+
+    /// Reads a `.json` or `.toml` preset file.
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Preset, PresetError> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => Preset::from_json_str(&src),
+            Some("toml") => Preset::from_toml_str(&src),
+            _ => Err(PresetError::UnknownFormat(path.display().to_string())),
+        }
+    }
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> Result<(), PresetError> {
+        let path = path.as_ref();
+        let dest = match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => self.to_json_string()?,
+            Some("toml") => self.to_toml_string()?,
+            _ => return Err(PresetError::UnknownFormat(path.display().to_string())),
+        };
+        std::fs::write(path, dest)?;
+        Ok(())
+    }
+
+    fn check_version(self) -> Result<Preset, PresetError> {
+        if self.version > PRESET_VERSION {
+            return Err(PresetError::UnsupportedVersion(self.version));
+        }
+        Ok(self)
+    }
+
+    /// Resolves a built-in preset name first, then falls back to a preset file path.
+    pub fn load_by_name(name: &str) -> Result<Preset, PresetError> {
+        match Preset::builtin(name) {
+            Err(PresetError::UnknownPreset(_)) if std::path::Path::new(name).is_file() => Preset::load(name),
+            res => res,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_presets_resolve() {
+        for name in BUILTIN_PRESETS {
+            assert_eq!(Preset::builtin(name).unwrap().name, name);
+        }
+        assert!(matches!(Preset::builtin("nope"), Err(PresetError::UnknownPreset(_))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn round_trip() {
+        for name in BUILTIN_PRESETS {
+            let preset = Preset::builtin(name).unwrap();
+            assert_eq!(Preset::from_json_str(&preset.to_json_string().unwrap()).unwrap(), preset);
+            assert_eq!(Preset::from_toml_str(&preset.to_toml_string().unwrap()).unwrap(), preset);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn rejects_newer_version_and_fills_defaults() {
+        let newer = format!("version = {}\nname = \"x\"\n[settings]\n", PRESET_VERSION + 1);
+        assert!(matches!(Preset::from_toml_str(&newer), Err(PresetError::UnsupportedVersion(_))));
+        let partial = Preset::from_toml_str("version = 1\nname = \"x\"\n[settings]\npitch_shift_ratio = 2.0\n").unwrap();
+        assert_eq!(partial.settings, VocoderSettings { pitch_shift_ratio: 2.0, ..VocoderSettings::default() });
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    sync::Arc,
+};
+
+use numpy::{
+    ndarray::{Array2, Axis},
+    IntoPyArray, PyArrayDyn, PyArrayMethods, PyReadonlyArrayDyn, PyUntypedArrayMethods,
+};
+use pyo3::{
+    exceptions::{PyRuntimeError, PyTypeError, PyValueError},
+    prelude::*,
+    types::PyDict,
+};
+use crate::block_adapter::BlockAdapter;
+use crate::preset::Preset;
+use crate::vulcan_helper::{enumerate_compute_devices, try_create_vulcan_device_on, try_create_vulcan_instance};
+use crate::vocoder::{
+    Vocoder, VocoderContext, VocoderError, VocoderSettings, DEFAULT_ANALYSIS_LENGTH, MAX_ANALYSIS_LENGTH, MIN_ANALYSIS_LENGTH,
+};
+
+
+#[pymethods]
+impl VocoderSettings {
+    /// VocoderSettings(**fields): any field not given keeps its default.
+    #[new]
+    #[pyo3(signature = (**kwargs))]
+    fn py_new(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut settings = VocoderSettings::default();
+        for (key, value) in kwargs.into_iter().flatten() {
+            match key.extract::<String>()?.as_str() {
+                "pitch_shift_ratio" => settings.pitch_shift_ratio = value.extract()?,
+                "delay" => settings.delay = value.extract()?,
+                "mix_span" => settings.mix_span = value.extract()?,
+                "equalizer" => settings.equalizer = value.extract()?,
+                "frequency_shift" => settings.frequency_shift = value.extract()?,
+                "frequency_shift_mode" => settings.frequency_shift_mode = value.extract()?,
+                "sample_rate" => settings.sample_rate = value.extract()?,
+                "resync_interval" => settings.resync_interval = value.extract()?,
+                "analysis_window" => settings.analysis_window = value.extract()?,
+                key => return Err(PyTypeError::new_err(format!("unknown setting \"{}\"", key))),
+            }
+        }
+        Ok(settings)
+    }
+
+    /// Built-in preset name or path to a .json/.toml preset file.
+    #[staticmethod]
+    fn preset(name: &str) -> PyResult<Self> {
+        Preset::load_by_name(name)
+            .map(|preset| preset.settings)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+        format!("{:?}", self)
+    }
+}
+
+
+/// Streams float32 audio of shape `(frames,)` or `(frames, channels)` through one vocoder per channel.
+/// Other Python threads keep running while a call waits on the GPU.
+#[pyclass(name = "Vocoder", unsendable)]
+struct PyVocoder {
+    // shared by the per-channel vocoders
+    context: Arc<VocoderContext>,
+    settings: VocoderSettings,
+    analysis_length: usize,
+    channels: Vec<BlockAdapter<Vocoder>>,
+}
+
+fn runtime_error(error: VocoderError) -> PyErr {
+    match error {
+        VocoderError::InvalidArgument(_) => PyValueError::new_err(error.to_string()),
+        _ => PyRuntimeError::new_err(error.to_string()),
+    }
+}
+
+impl PyVocoder {
+    fn ensure_channels(&mut self, count: usize) -> Result<(), VocoderError> {
+        while self.channels.len() < count {
+            let vocoder = Vocoder::try_with_context(&self.context, self.settings.clone(), self.analysis_length)?;
+            self.channels.push(BlockAdapter::new(vocoder));
+        }
+        Ok(())
+    }
+
+    // After a device loss, moves every channel to one new context, recovered once for all of them.
+    fn recover(&mut self) -> Result<(), VocoderError> {
+        if !self.channels.iter().any(|channel| channel.filter().is_lost()) {
+            return Ok(());
+        }
+        self.context = self.context.recover()?;
+        for channel in &mut self.channels {
+            channel.filter_mut().recover_on(&self.context)?;
+        }
+        Ok(())
+    }
+
+    // Waits on the GPU, so the callers run it detached from the interpreter for other threads to go on.
+    fn process_frames(&mut self, frames: &mut Array2<f32>) -> Result<(), VocoderError> {
+        self.recover()?;
+        self.ensure_channels(frames.ncols())?;
+        let mut scratch = vec![0.0f32; frames.nrows()];
+        for (mut column, channel) in frames.axis_iter_mut(Axis(1)).zip(self.channels.iter_mut()) {
+            scratch.iter_mut().zip(column.iter()).for_each(|(d, s)| *d = *s);
+            channel.process_in_place(&mut scratch);
+            column.iter_mut().zip(scratch.iter()).for_each(|(d, s)| *d = *s);
+        }
+        Ok(())
+    }
+
+    fn to_frames(samples: &PyReadonlyArrayDyn<'_, f32>) -> PyResult<Array2<f32>> {
+        let view = samples.as_array();
+        match view.ndim() {
+            1 => Ok(view.to_owned().into_shape_with_order((view.len(), 1)).unwrap()),
+            2 => Ok(view.to_owned().into_dimensionality().unwrap()),
+            n => Err(PyValueError::new_err(format!("expected a 1-D or 2-D array, got {}-D", n))),
+        }
+    }
+}
+
+#[pymethods]
+impl PyVocoder {
+    /// `device` is an index into `list_devices()`; the preferred device is used when omitted.
+    /// `analysis_length` is the number of frequency bins, a power of two from 256 to 4096.
+    #[new]
+    #[pyo3(signature = (settings = None, device = None, analysis_length = DEFAULT_ANALYSIS_LENGTH))]
+    fn new(settings: Option<VocoderSettings>, device: Option<usize>, analysis_length: usize) -> PyResult<Self> {
+        if !analysis_length.is_power_of_two() || !(MIN_ANALYSIS_LENGTH..=MAX_ANALYSIS_LENGTH).contains(&analysis_length) {
+            return Err(PyValueError::new_err(format!(
+                "analysis_length must be a power of two from {} to {}", MIN_ANALYSIS_LENGTH, MAX_ANALYSIS_LENGTH,
+            )));
+        }
+        let instance = try_create_vulcan_instance().map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        let mut devices = enumerate_compute_devices(&instance);
+        let index = device.unwrap_or(0);
+        if index >= devices.len() {
+            return Err(PyRuntimeError::new_err(format!("no Vulkan compute device #{}", index)));
+        }
+        let (physical_device, queue_family_index) = devices.swap_remove(index);
+        let (device, queues) = try_create_vulcan_device_on(physical_device, queue_family_index)
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(PyVocoder {
+            context: VocoderContext::new(device, queues),
+            settings: settings.unwrap_or_default(),
+            analysis_length,
+            channels: Vec::new(),
+        })
+    }
+
+    /// Samples by which `process` output lags its input.
+    #[getter]
+    fn latency(&self) -> usize {
+        BlockAdapter::<Vocoder>::LATENCY
+    }
+
+    #[getter]
+    fn analysis_length(&self) -> usize {
+        self.analysis_length
+    }
+
+    #[getter]
+    fn get_settings(&self) -> VocoderSettings {
+        self.settings.clone()
+    }
+    #[setter]
+    fn set_settings(&mut self, settings: VocoderSettings) {
+        self.channels.iter_mut().for_each(|channel| channel.filter_mut().update_settings(&settings));
+        self.settings = settings;
+    }
+
+    /// Clears the samples buffered between calls. The vocoders' own state carries over; `render` starts afresh.
+    fn reset(&mut self) {
+        self.channels.iter_mut().for_each(|channel| channel.reset());
+    }
+
+    /// Processes the next chunk of a stream. The output has the input's shape and lags it by `latency` samples.
+    /// Should the GPU be lost, the dry signal fades in until the next call moves to a new device,
+    /// which raises `RuntimeError` if there is none.
+    fn process<'py>(&mut self, py: Python<'py>, samples: PyReadonlyArrayDyn<'py, f32>) -> PyResult<Bound<'py, PyArrayDyn<f32>>> {
+        let shape = samples.shape().to_vec();
+        let mut frames = Self::to_frames(&samples)?;
+        py.detach(|| self.process_frames(&mut frames)).map_err(runtime_error)?;
+        frames.into_pyarray(py).reshape(shape)
+    }
+
+    /// Processes a whole signal and compensates the latency, so the output lines up with the input.
+    /// Each call starts from fresh vocoders: nothing carries over from earlier `process` or `render`
+    /// calls, so rendering the same signal twice gives the same output.
+    fn render<'py>(&mut self, py: Python<'py>, samples: PyReadonlyArrayDyn<'py, f32>) -> PyResult<Bound<'py, PyArrayDyn<f32>>> {
+        let shape = samples.shape().to_vec();
+        let frames = Self::to_frames(&samples)?;
+        let latency = BlockAdapter::<Vocoder>::LATENCY;
+        let mut padded = Array2::<f32>::zeros((frames.nrows() + latency, frames.ncols()));
+        padded.slice_mut(numpy::ndarray::s![..frames.nrows(), ..]).assign(&frames);
+        // the Fourier state and clock phase outlive `reset`, so start over from new vocoders,
+        // made from a recovered context if the device was lost
+        py.detach(|| {
+            self.recover()?;
+            self.channels.clear();
+            self.process_frames(&mut padded)
+        }).map_err(runtime_error)?;
+        let aligned = padded.slice(numpy::ndarray::s![latency.., ..]).to_owned();
+        aligned.into_pyarray(py).reshape(shape)
+    }
+}
+
+
+/// Names of the Vulkan devices usable by `Vocoder(device=...)`, preferred first.
+/// Empty when there is no Vulkan library.
+#[pyfunction]
+fn list_devices() -> Vec<String> {
+    let Ok(instance) = try_create_vulcan_instance() else {
+        return Vec::new();
+    };
+    enumerate_compute_devices(&instance)
+        .iter()
+        .map(|(device, _)| device.properties().device_name.clone())
+        .collect()
+}
+
+#[pymodule]
+fn vocoder_volcano(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<VocoderSettings>()?;
+    m.add_class::<crate::vocoder::FrequencyShiftMode>()?;
+    m.add_class::<crate::vocoder::AnalysisWindow>()?;
+    m.add_class::<PyVocoder>()?;
+    m.add_function(wrap_pyfunction!(list_devices, m)?)?;
+    m.add("BUILTIN_PRESETS", crate::preset::BUILTIN_PRESETS.to_vec())?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle, Thread},
+    time::{Duration, Instant},
+};
+
+use ringbuf::{HeapConsumer, HeapProducer, HeapRb};
+
+use crate::vocoder::{AudioFilter, BLOCK_LENGTH};
+
+
+#[derive(Default)]
+struct Stats {
+    input_overruns: AtomicU64,
+    output_overruns: AtomicU64,
+    output_underruns: AtomicU64,
+    blocks: AtomicU64,
+    worst_process_nanos: AtomicU64,
+    latency_samples: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StatsSnapshot {
+    /// Times captured samples were dropped because the worker fell behind.
+    pub input_overruns: u64,
+    /// Processed blocks dropped, in part or whole, because playback fell behind.
+    pub output_overruns: u64,
+    /// Playback callbacks that ran out of processed samples and played silence.
+    pub output_underruns: u64,
+    pub blocks: u64,
+    pub worst_process_time: Duration,
+    /// Samples queued between capture and playback, as last observed by the worker.
+    /// Stays at the `prefill` given to `Duplex::new` while the callbacks keep pace.
+    pub latency_samples: u64,
+}
+
+impl StatsSnapshot {
+    pub fn latency(&self, sample_rate: u32) -> Duration {
+        Duration::from_secs_f64(self.latency_samples as f64 / sample_rate as f64)
+    }
+}
+
+
+/// Capture side of a `Duplex`. Meant to be owned by the input callback; never blocks.
+pub struct InputEnd {
+    producer: HeapProducer<f32>,
+    stats: Arc<Stats>,
+    worker: Thread,
+}
+
+impl InputEnd {
+    pub fn push(&mut self, samples: &[f32]) {
+        if self.producer.push_slice(samples) < samples.len() {
+            self.stats.input_overruns.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.producer.len() >= BLOCK_LENGTH {
+            self.worker.unpark();
+        }
+    }
+}
+
+/// Playback side of a `Duplex`. Meant to be owned by the output callback; never blocks.
+pub struct OutputEnd {
+    consumer: HeapConsumer<f32>,
+    stats: Arc<Stats>,
+}
+
+impl OutputEnd {
+    /// Fills `dest` with processed samples, padding with silence on underrun.
+    pub fn pull(&mut self, dest: &mut [f32]) {
+        let popped = self.consumer.pop_slice(dest);
+        if popped < dest.len() {
+            dest[popped..].fill(0.0);
+            self.stats.output_underruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+
+/// Runs an `AudioFilter` on a worker thread between two lock-free ring buffers,
+/// so that audio callbacks only ever copy samples. The worker runs `AudioFilter::maintain`
+/// whenever it waits for input.
+pub struct Duplex {
+    stats: Arc<Stats>,
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Duplex {
+    /// `make_filter` runs on the worker thread, so the filter itself need not be `Send`.
+    /// `prefill` samples of silence are queued for playback to absorb scheduling jitter, and
+    /// every captured sample plays back behind them: the latency is exactly `prefill` samples,
+    /// which must therefore cover a block and the callback periods on both sides.
+    pub fn new<F, M>(make_filter: M, capacity: usize, prefill: usize) -> (Duplex, InputEnd, OutputEnd)
+    where
+        F: AudioFilter,
+        M: FnOnce() -> F + Send + 'static,
+    {
+        let capacity = capacity.max(prefill + 2 * BLOCK_LENGTH);
+        let (input_producer, mut input_consumer) = HeapRb::<f32>::new(capacity).split();
+        let (mut output_producer, output_consumer) = HeapRb::<f32>::new(capacity).split();
+        (0..prefill).for_each(|_| output_producer.push(0.0).unwrap());
+
+        let stats = Arc::new(Stats::default());
+        let running = Arc::new(AtomicBool::new(true));
+        let worker = {
+            let stats = stats.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                let mut filter = make_filter();
+                let mut src = [0.0f32; BLOCK_LENGTH];
+                let mut dest = [0.0f32; BLOCK_LENGTH];
+                while running.load(Ordering::Acquire) {
+                    if input_consumer.len() < BLOCK_LENGTH {
+                        // the callbacks only copy samples, so the worker may block here
+                        filter.maintain();
+                        thread::park_timeout(Duration::from_millis(1));
+                        continue;
+                    }
+                    input_consumer.pop_slice(&mut src);
+                    let start = Instant::now();
+                    filter.process(&src, &mut dest);
+                    let elapsed = start.elapsed().as_nanos() as u64;
+                    if output_producer.push_slice(&dest) < BLOCK_LENGTH {
+                        stats.output_overruns.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    let latency = input_consumer.len() + output_producer.len();
+                    stats.latency_samples.store(latency as u64, Ordering::Relaxed);
+                    stats.worst_process_nanos.fetch_max(elapsed, Ordering::Relaxed);
+                    stats.blocks.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        };
+
+        let input = InputEnd {
+            producer: input_producer,
+            stats: stats.clone(),
+            worker: worker.thread().clone(),
+        };
+        let output = OutputEnd {
+            consumer: output_consumer,
+            stats: stats.clone(),
+        };
+        let duplex = Duplex {
+            stats,
+            running,
+            worker: Some(worker),
+        };
+        (duplex, input, output)
+    }
+
+    pub fn stats(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            input_overruns: self.stats.input_overruns.load(Ordering::Relaxed),
+            output_overruns: self.stats.output_overruns.load(Ordering::Relaxed),
+            output_underruns: self.stats.output_underruns.load(Ordering::Relaxed),
+            blocks: self.stats.blocks.load(Ordering::Relaxed),
+            worst_process_time: Duration::from_nanos(self.stats.worst_process_nanos.load(Ordering::Relaxed)),
+            latency_samples: self.stats.latency_samples.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for Duplex {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Release);
+        if let Some(worker) = self.worker.take() {
+            worker.thread().unpark();
+            worker.join().ok();
+        }
+    }
+}
+
+
+/// Stand-in for an audio device: feeds `source` to `input` and collects `output`
+/// in callbacks of `period` samples, paced like a real device at `sample_rate`.
+pub struct NullBackend {
+    pub period: usize,
+    pub sample_rate: u32,
+}
+
+impl NullBackend {
+    pub fn run(&self, input: &mut InputEnd, output: &mut OutputEnd, source: &[f32]) -> Vec<f32> {
+        let period_time = Duration::from_secs_f64(self.period as f64 / self.sample_rate as f64);
+        let start = Instant::now();
+        let mut dest = vec![0.0f32; source.len()];
+        for (i, (src, dest)) in source.chunks(self.period).zip(dest.chunks_mut(self.period)).enumerate() {
+            input.push(src);
+            output.pull(dest);
+            let deadline = start + period_time * (i as u32 + 1);
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+        dest
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Passthrough;
+    impl AudioFilter for Passthrough {
+        fn process(&mut self, src: &[f32], dest: &mut [f32]) {
+            dest.copy_from_slice(src);
+        }
+    }
+
+    #[test]
+    fn null_backend_round_trip() {
+        let prefill = 4 * BLOCK_LENGTH;
+        let (duplex, mut input, mut output) = Duplex::new(|| Passthrough, 0, prefill);
+        let source: Vec<f32> = (0..48000).map(|i| (i as f32 * 0.01).sin()).collect();
+        let backend = NullBackend { period: 256, sample_rate: 48000 };
+        let dest = backend.run(&mut input, &mut output, &source);
+
+        let stats = duplex.stats();
+        assert_eq!(stats.input_overruns, 0);
+        assert_eq!(stats.output_overruns, 0);
+        assert_eq!(stats.output_underruns, 0);
+        assert!(dest[..prefill].iter().all(|&s| s == 0.0));
+        assert_eq!(&dest[prefill..], &source[..source.len() - prefill]);
+    }
+}
@@ -1,7 +1,7 @@
 
 
 mod samplewise_fourier;
-use samplewise_fourier::SamplewiseFourierDescriptorSets;
+use samplewise_fourier::{time_entry, Clock, SamplewiseFourierDescriptorSets, Staging};
 
 mod pitch_shift;
 use pitch_shift::PitchShiftDescriptorSets;
@@ -9,6 +9,28 @@ use pitch_shift::PitchShiftDescriptorSets;
 mod equalizer;
 use equalizer::EqualizerDescriptorSets;
 
+mod frequency_shift;
+use frequency_shift::FrequencyShiftDescriptorSets;
+
+mod analysis_window;
+use analysis_window::AnalysisWindowDescriptorSets;
+
+mod fallback;
+use fallback::Fallback;
+
+mod context;
+use context::Pipelines;
+pub use context::VocoderContext;
+
+mod pipeline_cache;
+
+mod recovery;
+use recovery::DeviceState;
+
+mod stats;
+use stats::Timings;
+pub use stats::{StageStats, VocoderStats};
+
 /*
 mod formant_warp;
 use formant_warp::FormantWarpDescriptorSets;
@@ -16,35 +38,95 @@ use formant_warp::FormantWarpDescriptorSets;
 
 
 use std::{
+    error::Error,
+    fmt,
     sync::Arc,
+    time::{Duration, Instant},
 };
 
 use vulkano::{
     command_buffer::{
-        allocator::{StandardCommandBufferAllocator}, AutoCommandBufferBuilder, CommandBufferUsage,
+        AutoCommandBufferBuilder, CommandBufferUsage, PrimaryAutoCommandBuffer,
     },
     descriptor_set::{
-        allocator::{StandardDescriptorSetAllocator}, PersistentDescriptorSet,
+        allocator::{StandardDescriptorSetAlloc, StandardDescriptorSetAllocator},
+        PersistentDescriptorSet,
     },
     device::{
         Device, DeviceOwned, Queue,
     },
-    memory::allocator::{StandardMemoryAllocator},
-    pipeline::{ComputePipeline, Pipeline, PipelineBindPoint},
-    sync::{self, GpuFuture},
+    pipeline::{Pipeline, PipelineBindPoint},
+    query::{GetResultsError, QueryPool, QueryPoolCreateInfo, QueryResultFlags, QueryType},
+    sync::{
+        self, Fence, FenceCreateInfo, FenceError, FlushError, GpuFuture, PipelineStage, Semaphore, SemaphoreCreateInfo,
+    },
+    OomError, VulkanError, VulkanObject,
 };
 
 
+/// Number of samples `Vocoder::process` consumes and produces per call.
+pub const BLOCK_LENGTH: usize = 1024;
+
+/// Analysis length `Vocoder::new` uses.
+pub const DEFAULT_ANALYSIS_LENGTH: usize = 1024;
+/// Bounds for `Vocoder::with_analysis_length`, which also needs a power of two.
+pub const MIN_ANALYSIS_LENGTH: usize = 256;
+pub const MAX_ANALYSIS_LENGTH: usize = 4096;
+// chunks each block is split into for the parallel scan; a workgroup covers `workgroup_size / SAMPLE_CHUNKS` bins
+const SAMPLE_CHUNKS: usize = 16;
+
+// blocks `render` puts in one submission; more would keep the GPU busy long enough for some
+// drivers to give up on it
+const RENDER_BATCH: usize = 256;
+
 pub trait AudioFilter {
     fn process(&mut self, src: &[f32], dest: &mut [f32]);
+    /// Work too slow for `process`, such as replacing a lost device. Hosts call it between
+    /// blocks from a thread that may block, never from the audio callback itself.
+    fn maintain(&mut self) {}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "python", pyo3::pyclass(eq, eq_int, from_py_object))]
+pub enum FrequencyShiftMode {
+    /// Moves every partial up (or down) by `frequency_shift` Hz.
+    SingleSideband,
+    /// Multiplies the output with a `frequency_shift` Hz carrier.
+    RingModulation,
 }
 
-#[derive(Clone)]
+/// Window the sliding DFT sees its input through. Tapered windows leak less between bins.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[cfg_attr(feature = "python", pyo3::pyclass(eq, eq_int, from_py_object))]
+pub enum AnalysisWindow {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+    /// Exponentially decaying sliding DFT; favours the most recent samples.
+    Exponential,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[cfg_attr(feature = "python", pyo3::pyclass(get_all, set_all, from_py_object))]
 pub struct VocoderSettings {
     pub pitch_shift_ratio: f32,
     pub delay: f32,
     pub mix_span: f32,
     pub equalizer: [f32; 8],
+    /// Linear frequency offset in Hz, applied after the pitch shift.
+    pub frequency_shift: f32,
+    pub frequency_shift_mode: FrequencyShiftMode,
+    pub sample_rate: f32,
+    /// Blocks between exact recomputations of the running Fourier state, 0 to never resync.
+    pub resync_interval: u32,
+    pub analysis_window: AnalysisWindow,
 }
 impl Default for VocoderSettings {
     fn default() -> Self {
@@ -53,20 +135,140 @@ impl Default for VocoderSettings {
             delay: 341.0,
             mix_span: 0.9,
             equalizer: [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            frequency_shift: 0.0,
+            frequency_shift_mode: FrequencyShiftMode::SingleSideband,
+            sample_rate: 48000.0,
+            resync_interval: 16,
+            analysis_window: AnalysisWindow::Rectangular,
         }
     }
 }
 
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum VocoderError {
+    /// The device was lost, e.g. to a driver reset; `Vocoder::recover` moves to a new one.
+    DeviceLost,
+    /// `VocoderContext::recover` found no device it could create.
+    NoDevice,
+    /// A setting or size outside what the vocoder supports, e.g. an analysis length that is no
+    /// power of two.
+    InvalidArgument(&'static str),
+    /// `Vocoder::submit` found both blocks still in flight, or `Vocoder::render` found a block
+    /// not collected yet: it must be collected first.
+    Busy,
+    Vulkan(VulkanError),
+}
+
+impl fmt::Display for VocoderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VocoderError::DeviceLost => write!(f, "the Vulkan device was lost"),
+            VocoderError::NoDevice => write!(f, "no Vulkan device to recover on"),
+            VocoderError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            VocoderError::Busy => write!(f, "two blocks are in flight already"),
+            VocoderError::Vulkan(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for VocoderError {}
+
+impl From<ash::vk::Result> for VocoderError {
+    fn from(result: ash::vk::Result) -> Self {
+        VulkanError::from(result).into()
+    }
+}
+
+impl From<VulkanError> for VocoderError {
+    fn from(error: VulkanError) -> Self {
+        match error {
+            VulkanError::DeviceLost => VocoderError::DeviceLost,
+            error => VocoderError::Vulkan(error),
+        }
+    }
+}
+
+// Vulkan's own error behind one of vulkano's errors from setting up. Anything else vulkano
+// rejects is a bug here rather than a failing device, and reported as a failed initialisation.
+fn setup_error(error: impl Error + 'static) -> VocoderError {
+    let mut source: Option<&(dyn Error + 'static)> = Some(&error);
+    while let Some(error) = source {
+        if let Some(&error) = error.downcast_ref::<VulkanError>() {
+            return error.into();
+        }
+        match error.downcast_ref::<OomError>() {
+            Some(OomError::OutOfHostMemory) => return VocoderError::Vulkan(VulkanError::OutOfHostMemory),
+            Some(OomError::OutOfDeviceMemory) => return VocoderError::Vulkan(VulkanError::OutOfDeviceMemory),
+            None => {}
+        }
+        // lost devices vulkano reports without a source to look into
+        if matches!(error.downcast_ref::<FlushError>(), Some(FlushError::DeviceLost))
+            || matches!(error.downcast_ref::<FenceError>(), Some(FenceError::DeviceLost))
+        {
+            return VocoderError::DeviceLost;
+        }
+        source = error.source();
+    }
+    VocoderError::Vulkan(VulkanError::InitializationFailed)
+}
+
+// What `Vocoder::with_streams` cannot build a vocoder for.
+fn check_streams(settings: &[VocoderSettings], analysis_length: usize) -> Result<(), VocoderError> {
+    if settings.is_empty() {
+        return Err(VocoderError::InvalidArgument("a vocoder needs at least one stream"));
+    }
+    if !analysis_length.is_power_of_two() || !(MIN_ANALYSIS_LENGTH..=MAX_ANALYSIS_LENGTH).contains(&analysis_length) {
+        return Err(VocoderError::InvalidArgument(
+            "the analysis length must be a power of two from MIN_ANALYSIS_LENGTH to MAX_ANALYSIS_LENGTH",
+        ));
+    }
+    Ok(())
+}
+
+/// A block handed to `Vocoder::submit`, redeemed for its output with `Vocoder::collect`.
+#[must_use = "a submitted block must be collected before its slot is reused"]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ticket {
+    block: u64,
+}
+
 pub struct Vocoder {
+    context: Arc<VocoderContext>,
     vulkan_device: Arc<Device>,
     queue: Arc<Queue>,
-    command_buffer_allocator: StandardCommandBufferAllocator,
-    pipeline_ft: Arc<ComputePipeline>,
-    pipeline_vocoder: Arc<ComputePipeline>,
-    descriptor_sets_ft: Vec<Arc<PersistentDescriptorSet>>,
-    descriptor_sets_vocoder: Vec<Arc<PersistentDescriptorSet>>,
+    // recorded once, indexed by block parity like `SamplewiseFourierDescriptorSets::descriptor_sets_vocoder`;
+    // they keep the pipelines and descriptor sets alive
+    command_buffers: [Arc<PrimaryAutoCommandBuffer>; 2],
+    fences: [Fence; 2],
+    // each submission signals its parity's semaphore and waits on the other's, so blocks in flight
+    // still run one after another on the device
+    semaphores: [Semaphore; 2],
+    state: DeviceState,
+    deadline: Option<Duration>,
+    deadline_misses: u64,
+    fallback: Fallback,
+    dispatches: Dispatches,
+    // three per parity, around the two dispatches; `None` if the queue cannot write timestamps
+    timestamps: Option<Timestamps>,
+    submitted_at: [Instant; 2],
+    // the commands and buffers of a `render` batch whose wait failed, kept until its fence signals
+    rendering: Option<(PrimaryAutoCommandBuffer, Staging)>,
+    // `Some` while statistics are enabled
+    timings: Option<Box<Timings>>,
     samplewise_fourier_descriptor_sets: SamplewiseFourierDescriptorSets<StandardDescriptorSetAllocator>,
+    pitch_shift_descriptor_sets: PitchShiftDescriptorSets<StandardDescriptorSetAllocator>,
+    equalizer_descriptor_sets: EqualizerDescriptorSets<StandardDescriptorSetAllocator>,
+    frequency_shift_descriptor_sets: FrequencyShiftDescriptorSets<StandardDescriptorSetAllocator>,
+    analysis_window_descriptor_sets: AnalysisWindowDescriptorSets<StandardDescriptorSetAllocator>,
+    // parities whose settings buffers lag behind `settings`; written as the parity's next block is submitted
+    settings_pending: [bool; 2],
+    // the rest is per stream; a plain vocoder is a batch of one
+    settings: Vec<VocoderSettings>,
+    analysis_length: usize,
+    clocks: Vec<Clock>,
+    // set when the running state was built for another window and must be recomputed
+    resync_pending: Vec<bool>,
 }
 
 unsafe impl DeviceOwned for Vocoder {
@@ -76,139 +278,915 @@ unsafe impl DeviceOwned for Vocoder {
 }
 
 impl AudioFilter for Vocoder {
+    /// Never fails: errors play the fallback signal. A lost device stays lost, playing the
+    /// fallback, until `maintain` or the host recovers it.
+    fn process(&mut self, src: &[f32], dest: &mut [f32]) {
+        let _ = self.try_process(src, dest);
+    }
+
+    /// Recovers a lost device, backing off after each failed attempt.
+    fn maintain(&mut self) {
+        if self.state.recovery_due(Instant::now()) {
+            let _ = self.recover();
+        }
+    }
+}
+
+/// Keeps one block in flight on the GPU: each call submits its input and returns the output of
+/// the previous call, so uploading and reading back overlap with the compute work.
+/// Output is late by `LATENCY` samples on top of the vocoder's own delay; the first block is silent.
+pub struct PipelinedVocoder {
+    vocoder: Vocoder,
+    pending: Option<Ticket>,
+}
+
+impl PipelinedVocoder {
+    pub const LATENCY: usize = BLOCK_LENGTH;
+
+    pub fn new(vocoder: Vocoder) -> Self {
+        PipelinedVocoder { vocoder, pending: None }
+    }
+    pub fn vocoder(&self) -> &Vocoder {
+        &self.vocoder
+    }
+    pub fn vocoder_mut(&mut self) -> &mut Vocoder {
+        &mut self.vocoder
+    }
+}
+
+impl PipelinedVocoder {
+    fn try_process(&mut self, src: &[f32], dest: &mut [f32]) -> Result<(), VocoderError> {
+        self.vocoder.retire_late()?;
+        let ticket = self.vocoder.submit(src)?;
+        match self.pending.replace(ticket) {
+            Some(previous) => {
+                self.vocoder.collect(previous, dest)?;
+                self.vocoder.fallback.on_time(&src[..BLOCK_LENGTH], &mut dest[..BLOCK_LENGTH]);
+            }
+            None => dest[..BLOCK_LENGTH].fill(0.0),
+        }
+        Ok(())
+    }
+}
+
+impl AudioFilter for PipelinedVocoder {
     fn process(&mut self, src: &[f32], dest: &mut [f32]) {
-        self.samplewise_fourier_descriptor_sets.update_input(src);
-        self.process_gpu();
-        let dest_buffer_content = self.samplewise_fourier_descriptor_sets.result_ift.read().unwrap();
-        for i in 0..1024 {
-            dest[i] = dest_buffer_content[i];
+        if self.try_process(src, dest).is_err() {
+            // whatever is still in flight is retired once it finishes, as late blocks are
+            self.pending = None;
+            self.vocoder.state.late = self.vocoder.state.in_flight;
+            self.vocoder.fallback.missed(&src[..BLOCK_LENGTH], &mut dest[..BLOCK_LENGTH]);
+        }
+    }
+
+    fn maintain(&mut self) {
+        self.vocoder.maintain();
+    }
+}
+
+/// Independent mono streams, each with its own settings and state, processed together by one
+/// submission per block. Serves many voices on one GPU better than a `Vocoder` each.
+pub struct VocoderBatch {
+    vocoder: Vocoder,
+}
+
+impl VocoderBatch {
+    /// One stream per entry of `settings`; `analysis_length` as for `Vocoder::with_analysis_length`.
+    /// `VocoderError::InvalidArgument` if `settings` is empty or the analysis length unsupported.
+    pub fn new(
+        context: &Arc<VocoderContext>, settings: &[VocoderSettings], analysis_length: usize,
+    ) -> Result<Self, VocoderError> {
+        Ok(VocoderBatch { vocoder: Vocoder::with_streams(context, settings.to_vec(), analysis_length)? })
+    }
+
+    pub fn streams(&self) -> usize {
+        self.vocoder.settings.len()
+    }
+    pub fn settings(&self, stream: usize) -> &VocoderSettings {
+        &self.vocoder.settings[stream]
+    }
+    /// Applies new settings to one stream from the next `process` call on.
+    pub fn update_settings(&mut self, stream: usize, settings: &VocoderSettings) {
+        self.vocoder.update_stream_settings(stream, settings);
+    }
+
+    /// Processes one block of `BLOCK_LENGTH` samples per stream, `src[k]` into `dest[k]`.
+    /// After `VocoderError::DeviceLost`, `recover` before processing again.
+    pub fn process(&mut self, src: &[&[f32]], dest: &mut [&mut [f32]]) -> Result<(), VocoderError> {
+        assert!(
+            src.len() == self.streams() && dest.len() == self.streams(),
+            "expected a block for each of the {} streams", self.streams(),
+        );
+        let ticket = self.vocoder.submit_streams(src)?;
+        self.vocoder.collect_streams(ticket, dest)
+    }
+
+    /// See `Vocoder::recover`.
+    pub fn recover(&mut self) -> Result<(), VocoderError> {
+        self.vocoder.recover()
+    }
+    /// See `Vocoder::recover_on`.
+    pub fn recover_on(&mut self, context: &Arc<VocoderContext>) -> Result<(), VocoderError> {
+        self.vocoder.recover_on(context)
+    }
+    pub fn is_lost(&self) -> bool {
+        self.vocoder.is_lost()
+    }
+    pub fn device_losses(&self) -> u64 {
+        self.vocoder.device_losses()
+    }
+    /// See `Vocoder::set_stats_enabled`; times whole submissions of every stream.
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.vocoder.set_stats_enabled(enabled);
+    }
+    pub fn stats(&self) -> VocoderStats {
+        self.vocoder.stats()
+    }
+}
+
+// What every block runs; recorded once per parity for `process`, and again for each block `render` batches.
+struct Dispatches {
+    pipelines: Pipelines,
+    groups_vocoder: u32,
+    groups_resolve: u32,
+    streams: u32,
+    // per parity, in set order
+    descriptor_sets_vocoder: [Vec<Arc<PersistentDescriptorSet<StandardDescriptorSetAlloc>>>; 2],
+    descriptor_sets_resolve: [Vec<Arc<PersistentDescriptorSet<StandardDescriptorSetAlloc>>>; 2],
+}
+
+impl Dispatches {
+    // Records the parity's two passes over every stream, between the parity's three timestamps if given.
+    fn record(
+        &self, builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, parity: usize,
+        timestamps: Option<&Timestamps>,
+    ) {
+        let first_query = parity as u32 * 3;
+        // the first once the commands start, the others once everything before them finished
+        let write_timestamp = |builder: &mut AutoCommandBufferBuilder<PrimaryAutoCommandBuffer>, query, stage| {
+            if let Some(timestamps) = timestamps {
+                // reset right below, before the first write
+                unsafe {
+                    builder.write_timestamp(timestamps.query_pool.clone(), query, stage).unwrap();
+                }
+            }
+        };
+        if let Some(timestamps) = timestamps {
+            // queries must be reset before each write, and this runs once per block
+            unsafe {
+                builder.reset_query_pool(timestamps.query_pool.clone(), first_query..first_query + 3).unwrap();
+            }
         }
+        write_timestamp(builder, first_query, PipelineStage::TopOfPipe);
+        builder
+            .bind_pipeline_compute(self.pipelines.vocoder.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipelines.vocoder.layout().clone(),
+                0,
+                self.descriptor_sets_vocoder[parity].clone(),
+            )
+            .dispatch([self.groups_vocoder, self.streams, 1]).expect("failed to dispatch");
+        write_timestamp(builder, first_query + 1, PipelineStage::BottomOfPipe);
+        builder
+            .bind_pipeline_compute(self.pipelines.resolve.clone())
+            .bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipelines.resolve.layout().clone(),
+                0,
+                self.descriptor_sets_resolve[parity].clone(),
+            )
+            .dispatch([self.groups_resolve, self.streams, 1]).expect("failed to dispatch");
+        write_timestamp(builder, first_query + 2, PipelineStage::BottomOfPipe);
+    }
+}
+
+struct Timestamps {
+    query_pool: Arc<QueryPool>,
+    // nanoseconds per tick
+    period: f64,
+    // the bits of a timestamp the queue actually writes
+    mask: u64,
+}
+
+impl Timestamps {
+    fn new(queue: &Queue) -> Result<Option<Timestamps>, VocoderError> {
+        let physical_device = queue.device().physical_device();
+        let Some(bits) = physical_device.queue_family_properties()[queue.queue_family_index() as usize].timestamp_valid_bits
+        else {
+            return Ok(None);
+        };
+        let query_pool = QueryPool::new(queue.device().clone(), QueryPoolCreateInfo {
+            query_count: 6,
+            ..QueryPoolCreateInfo::query_type(QueryType::Timestamp)
+        }).map_err(setup_error)?;
+        Ok(Some(Timestamps {
+            query_pool,
+            period: physical_device.properties().timestamp_period as f64,
+            mask: u64::MAX >> (64 - bits.min(64)),
+        }))
+    }
+
+    // Durations of the parity's two dispatches, once its block finished; the failure is for `check`.
+    fn dispatch_times(&self, parity: usize) -> Result<Option<[Duration; 2]>, ash::vk::Result> {
+        let first_query = parity as u32 * 3;
+        let mut ticks = [0u64; 3];
+        let queries = self.query_pool.queries_range(first_query..first_query + 3).unwrap();
+        let available = queries.get_results(&mut ticks, QueryResultFlags::empty()).map_err(|error| match error {
+            GetResultsError::DeviceLost => ash::vk::Result::ERROR_DEVICE_LOST,
+            GetResultsError::OomError(OomError::OutOfHostMemory) => ash::vk::Result::ERROR_OUT_OF_HOST_MEMORY,
+            GetResultsError::OomError(OomError::OutOfDeviceMemory) => ash::vk::Result::ERROR_OUT_OF_DEVICE_MEMORY,
+            // the queries and buffer are fixed, so anything else is a bug here; `check` reports
+            // it as a failure rather than bringing the audio thread down
+            _ => ash::vk::Result::ERROR_UNKNOWN,
+        })?;
+        if !available {
+            return Ok(None);
+        }
+        let elapsed = |from: u64, to: u64| {
+            Duration::from_nanos(((to.wrapping_sub(from) & self.mask) as f64 * self.period) as u64)
+        };
+        Ok(Some([elapsed(ticks[0], ticks[1]), elapsed(ticks[1], ticks[2])]))
     }
 }
 
 impl Vocoder {
     pub fn new(queue: Arc<Queue>, settings: VocoderSettings) -> Vocoder {
-        let device = queue.device();
-        let memory_allocator = StandardMemoryAllocator::new_default(device.clone());
-        let descriptor_set_allocator = StandardDescriptorSetAllocator::new(device.clone());
-        let command_buffer_allocator =
-            StandardCommandBufferAllocator::new(device.clone(), Default::default());
-        let (pipeline_ft, pipeline_vocoder) = Self::create_pipelines(device);
-        let set_layouts_ft = pipeline_ft.layout().set_layouts();
-        let set_layouts_vocoder= pipeline_vocoder.layout().set_layouts();
+        Self::with_analysis_length(queue, settings, DEFAULT_ANALYSIS_LENGTH)
+    }
+
+    /// `analysis_length` is the number of frequency bins and the span of the analysis in samples:
+    /// longer resolves lower voices, shorter follows transients more closely.
+    /// It must be a power of two from `MIN_ANALYSIS_LENGTH` to `MAX_ANALYSIS_LENGTH`;
+    /// `try_with_context` returns `VocoderError::InvalidArgument` where this panics.
+    pub fn with_analysis_length(queue: Arc<Queue>, settings: VocoderSettings, analysis_length: usize) -> Vocoder {
+        let context = VocoderContext::new(queue.device().clone(), [queue]);
+        Self::with_context(&context, settings, analysis_length)
+    }
+
+    /// Like `with_analysis_length`, on the next queue of `context` and with its pipelines.
+    pub fn with_context(context: &Arc<VocoderContext>, settings: VocoderSettings, analysis_length: usize) -> Vocoder {
+        Self::try_with_context(context, settings, analysis_length).unwrap()
+    }
+
+    /// Like `with_context`, for hosts that must not panic when the device fails.
+    pub fn try_with_context(
+        context: &Arc<VocoderContext>, settings: VocoderSettings, analysis_length: usize,
+    ) -> Result<Vocoder, VocoderError> {
+        Self::with_streams(context, vec![settings], analysis_length)
+    }
+
+    // one stream per entry of `settings`
+    fn with_streams(
+        context: &Arc<VocoderContext>, settings: Vec<VocoderSettings>, analysis_length: usize,
+    ) -> Result<Vocoder, VocoderError> {
+        check_streams(&settings, analysis_length)?;
+        let queue = context.next_queue();
+        let device = context.device();
+        let memory_allocator = &context.memory_allocator;
+        let descriptor_set_allocator = &context.descriptor_set_allocator;
+        let command_buffer_allocator = &context.command_buffer_allocator;
+        let pipelines = context.pipelines(analysis_length)?;
+        let workgroup_size = pipelines.workgroup_size;
+        let streams = settings.len();
+        let set_layouts_vocoder = pipelines.vocoder.layout().set_layouts();
+        let set_layouts_resolve = pipelines.resolve.layout().set_layouts();
         let mut command_buffer_builder = AutoCommandBufferBuilder::primary(
-            &command_buffer_allocator,
+            command_buffer_allocator,
             queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
-        ).unwrap();
+        ).map_err(setup_error)?;
 
         let samplewise_fourier_descriptor_sets = SamplewiseFourierDescriptorSets::new(
-            1024,
-            &memory_allocator, &descriptor_set_allocator, &mut command_buffer_builder,
-            set_layouts_ft.get(0).unwrap().clone(), set_layouts_vocoder.get(0).unwrap().clone(),
-        );
+            BLOCK_LENGTH, analysis_length, analysis_length * SAMPLE_CHUNKS / workgroup_size, streams,
+            memory_allocator, descriptor_set_allocator, &mut command_buffer_builder,
+            set_layouts_vocoder.get(0).unwrap().clone(), set_layouts_resolve.get(0).unwrap().clone(),
+        )?;
+        // every stream starts out with the first one's settings, until the first submission writes its own
+        let first = &settings[0];
         let pitch_shift_descriptor_sets = PitchShiftDescriptorSets::new(
-            settings.pitch_shift_ratio, settings.delay, settings.mix_span,
-            &memory_allocator, &descriptor_set_allocator, &mut command_buffer_builder,
-            set_layouts_ft.get(1).unwrap().clone(), set_layouts_vocoder.get(1).unwrap().clone(),
-        );
+            first.pitch_shift_ratio, first.delay, first.mix_span, streams,
+            memory_allocator, descriptor_set_allocator,
+            set_layouts_vocoder.get(1).unwrap().clone(),
+        )?;
         let equalizer_descriptor_sets = EqualizerDescriptorSets::new(
-            settings.equalizer.clone(),
-            &memory_allocator, &descriptor_set_allocator, &mut command_buffer_builder,
+            first.equalizer, streams,
+            memory_allocator, descriptor_set_allocator,
             set_layouts_vocoder.get(2).unwrap().clone(),
-        );
+        )?;
+        let frequency_shift_descriptor_sets = FrequencyShiftDescriptorSets::new(
+            first.frequency_shift, first.frequency_shift_mode, first.sample_rate, streams,
+            memory_allocator, descriptor_set_allocator,
+            set_layouts_vocoder.get(3).unwrap().clone(),
+        )?;
+        let analysis_window_descriptor_sets = AnalysisWindowDescriptorSets::new(
+            first.analysis_window, analysis_length, streams,
+            memory_allocator, descriptor_set_allocator,
+            set_layouts_vocoder.get(4).unwrap().clone(),
+        )?;
 
         sync::now(device.clone())
-            .then_execute(queue.clone(), command_buffer_builder.build().unwrap()).unwrap()
-            .then_signal_fence_and_flush().unwrap()
-            .wait(None).unwrap()
+            .then_execute(queue.clone(), command_buffer_builder.build().map_err(setup_error)?).map_err(setup_error)?
+            .then_signal_fence_and_flush().map_err(setup_error)?
+            .wait(None).map_err(setup_error)?
         ;
 
-        let descriptor_sets_ft = vec![
-            samplewise_fourier_descriptor_sets.descriptor_set_ft.clone(),
-            pitch_shift_descriptor_sets.descriptor_set_ft.clone(),
-        ];
-        let descriptor_sets_vocoder = vec![
-            samplewise_fourier_descriptor_sets.descriptor_set_ift.clone(),
-            pitch_shift_descriptor_sets.descriptor_set_ift.clone(),
-            equalizer_descriptor_sets.descriptor_set.clone(),
-        ];
-
-        Vocoder {
+        let descriptor_sets_vocoder = [0, 1].map(|parity| vec![
+            samplewise_fourier_descriptor_sets.descriptor_sets_vocoder[parity].clone(),
+            pitch_shift_descriptor_sets.descriptor_sets[parity].clone(),
+            equalizer_descriptor_sets.descriptor_sets[parity].clone(),
+            frequency_shift_descriptor_sets.descriptor_sets[parity].clone(),
+            analysis_window_descriptor_sets.descriptor_sets[parity].clone(),
+        ]);
+        let descriptor_sets_resolve = samplewise_fourier_descriptor_sets.descriptor_sets_resolve.clone()
+            .map(|set| vec![set]);
+        let dispatches = Dispatches {
+            groups_vocoder: (analysis_length * SAMPLE_CHUNKS / workgroup_size) as u32,
+            groups_resolve: (BLOCK_LENGTH / workgroup_size) as u32,
+            streams: streams as u32,
+            pipelines,
+            descriptor_sets_vocoder,
+            descriptor_sets_resolve,
+        };
+        let timestamps = Timestamps::new(&queue)?;
+        // settings only ever change buffer contents, so the same commands serve every block
+        let command_buffer = |parity| {
+            let mut builder = AutoCommandBufferBuilder::primary(
+                command_buffer_allocator,
+                queue.queue_family_index(),
+                CommandBufferUsage::MultipleSubmit,
+            ).map_err(setup_error)?;
+            // written whether or not statistics are enabled, which saves recording twice
+            dispatches.record(&mut builder, parity, timestamps.as_ref());
+            builder.build().map(Arc::new).map_err(setup_error)
+        };
+        let command_buffers = [command_buffer(0)?, command_buffer(1)?];
+        let fence = || Fence::new(device.clone(), FenceCreateInfo::default()).map_err(setup_error);
+        let fences = [fence()?, fence()?];
+        let semaphore = || Semaphore::new(device.clone(), SemaphoreCreateInfo::default()).map_err(setup_error);
+        let semaphores = [semaphore()?, semaphore()?];
+
+        Ok(Vocoder {
+            context: context.clone(),
             vulkan_device: device.clone(),
             queue: queue,
-            command_buffer_allocator: command_buffer_allocator,
-            pipeline_ft: pipeline_ft, pipeline_vocoder: pipeline_vocoder,
-            descriptor_sets_ft: descriptor_sets_ft, descriptor_sets_vocoder: descriptor_sets_vocoder,
-            samplewise_fourier_descriptor_sets: samplewise_fourier_descriptor_sets
-        }
-    }
-    fn create_pipelines(device: &Arc<Device>) -> (Arc<ComputePipeline>, Arc<ComputePipeline>) {
-        let pipeline_ft = {
-            mod cs {
-                vulkano_shaders::shader! {
-                    ty: "compute",
-                    path: "src/vocoder/samplewise-fourier.glsl.comp",
-                }
+            command_buffers,
+            fences,
+            semaphores,
+            state: DeviceState::new(0),
+            deadline: None,
+            deadline_misses: 0,
+            fallback: Fallback::new(BLOCK_LENGTH),
+            dispatches,
+            timestamps,
+            submitted_at: [Instant::now(); 2],
+            rendering: None,
+            timings: None,
+            samplewise_fourier_descriptor_sets: samplewise_fourier_descriptor_sets,
+            pitch_shift_descriptor_sets,
+            equalizer_descriptor_sets,
+            frequency_shift_descriptor_sets,
+            analysis_window_descriptor_sets,
+            settings_pending: [streams > 1; 2],
+            settings,
+            analysis_length,
+            clocks: vec![Clock::new(analysis_length); streams],
+            resync_pending: vec![false; streams],
+        })
+    }
+
+    pub fn settings(&self) -> &VocoderSettings {
+        &self.settings[0]
+    }
+    pub fn analysis_length(&self) -> usize {
+        self.analysis_length
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+    /// Bounds how long `process` waits for the GPU, `None` to wait as long as it takes.
+    /// Blocks that miss the deadline are replaced by a crossfade from the last good output to
+    /// the dry input; the GPU's work on them is discarded once it finishes, and the vocoder
+    /// fades back in from the first block that makes it in time.
+    pub fn set_deadline(&mut self, deadline: Option<Duration>) {
+        self.deadline = deadline;
+    }
+    /// Blocks `process` replaced with the fallback signal since the vocoder was created.
+    pub fn deadline_misses(&self) -> u64 {
+        self.deadline_misses
+    }
+
+    /// Starts or stops timing blocks for `stats`; off by default.
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        if enabled != self.timings.is_some() {
+            self.timings = enabled.then(|| Box::new(Timings::new()));
+        }
+    }
+    /// Timing of the last blocks, all `None` unless enabled with `set_stats_enabled`.
+    /// The dispatches are timed on the GPU with timestamp queries, everything else by the wall clock.
+    pub fn stats(&self) -> VocoderStats {
+        self.timings.as_ref().map_or_else(VocoderStats::default, |timings| timings.stats())
+    }
+
+    /// Applies new settings from the next `process` call on, without resetting the stream.
+    /// A new analysis window makes the next block end with a resync, as the running state no
+    /// longer matches it. Blocks already submitted keep the settings they were submitted with.
+    pub fn update_settings(&mut self, settings: &VocoderSettings) {
+        self.update_stream_settings(0, settings);
+    }
+
+    fn update_stream_settings(&mut self, stream: usize, settings: &VocoderSettings) {
+        if settings.analysis_window != self.settings[stream].analysis_window {
+            self.resync_pending[stream] = true;
+        }
+        self.settings[stream] = settings.clone();
+        self.settings_pending = [true; 2];
+    }
+
+    // Copies every stream's settings to the parity's buffers the shader reads them from,
+    // if they changed since. The parity's block must not be in flight.
+    fn write_settings(&mut self, parity: usize) {
+        if !self.settings_pending[parity] {
+            return;
+        }
+        for (stream, settings) in self.settings.iter().enumerate() {
+            self.pitch_shift_descriptor_sets.update(
+                parity, stream, settings.pitch_shift_ratio, settings.delay, settings.mix_span,
+            );
+            self.equalizer_descriptor_sets.update(parity, stream, settings.equalizer);
+            self.frequency_shift_descriptor_sets.update(
+                parity, stream, settings.frequency_shift, settings.frequency_shift_mode, settings.sample_rate,
+            );
+            self.analysis_window_descriptor_sets.update(parity, stream, settings.analysis_window);
+        }
+        self.settings_pending[parity] = false;
+    }
+    /// Like `process`, but reports Vulkan failures instead of handling them. `dest` then holds
+    /// the fallback signal; after `VocoderError::DeviceLost` every call fails until `recover`.
+    pub fn try_process(&mut self, src: &[f32], dest: &mut [f32]) -> Result<(), VocoderError> {
+        let (src, dest) = (&src[..BLOCK_LENGTH], &mut dest[..BLOCK_LENGTH]);
+        let result = self.process_gpu(src, dest);
+        if result.is_err() {
+            self.fallback.missed(src, dest);
+        }
+        result
+    }
+
+    fn process_gpu(&mut self, src: &[f32], dest: &mut [f32]) -> Result<(), VocoderError> {
+        self.retire_late()?;
+        let parity = self.parity();
+        if self.state.in_flight[parity] {
+            // still two blocks behind: skip this one instead of queueing more work
+            self.deadline_misses += 1;
+            self.fallback.missed(src, dest);
+            return Ok(());
+        }
+        // collected right here, or by `retire_late` once it finishes if it misses the deadline
+        let _ = self.submit(src)?;
+        if self.wait_gpu(parity, self.deadline)? {
+            let readback_started = Instant::now();
+            self.samplewise_fourier_descriptor_sets.read_result(parity, 0, dest);
+            if let Some(timings) = &mut self.timings {
+                timings.readback.record(readback_started.elapsed());
             }
-            let shader = cs::load(device.clone()).unwrap();
-            ComputePipeline::new(
-                device.clone(),
-                shader.entry_point("main").unwrap(),
-                &(), None, |_| {},
-            ).unwrap()
-        };
-    
-        let pipeline_ift = {
-            mod cs {
-                vulkano_shaders::shader! {
-                    ty: "compute",
-                    path: "src/vocoder/vocoder.glsl.comp",
+            self.fallback.on_time(src, dest);
+        } else {
+            self.state.late[parity] = true;
+            self.deadline_misses += 1;
+            self.fallback.missed(src, dest);
+        }
+        Ok(())
+    }
+
+    /// Processes a whole signal at once for offline use, recording many blocks into each
+    /// submission instead of submitting them one by one. The output is bit for bit what `process`
+    /// gives block by block, with the last block padded with silence, and the stream carries on
+    /// from there. Failures are handled as in `process`, which takes over for the failed batch.
+    /// Not counted in `stats`.
+    ///
+    /// Every ticket from `submit` must be collected first, or this returns `VocoderError::Busy`
+    /// without processing anything.
+    pub fn render(&mut self, src: &[f32]) -> Result<Vec<f32>, VocoderError> {
+        // late blocks are the vocoder's own to collect
+        if (0..2).any(|parity| self.state.in_flight[parity] && !self.state.late[parity]) {
+            return Err(VocoderError::Busy);
+        }
+        let blocks = src.len().div_ceil(BLOCK_LENGTH);
+        let mut padded = src.to_vec();
+        padded.resize(blocks * BLOCK_LENGTH, 0.0);
+        let mut dest = vec![0.0f32; padded.len()];
+        let timings = self.timings.take();
+        let batch_length = RENDER_BATCH * BLOCK_LENGTH;
+        for (src, dest) in padded.chunks(batch_length).zip(dest.chunks_mut(batch_length)) {
+            if self.render_gpu(src, dest).is_err() {
+                for (src, dest) in src.chunks(BLOCK_LENGTH).zip(dest.chunks_mut(BLOCK_LENGTH)) {
+                    self.process(src, dest);
                 }
             }
-            let shader = cs::load(device.clone()).unwrap();
-            ComputePipeline::new(
-                device.clone(),
-                shader.entry_point("main").unwrap(),
-                &(), None, |_| {},
-            ).unwrap()
-        };
-    
-        (pipeline_ft, pipeline_ift)
+        }
+        self.timings = timings;
+        dest.truncate(src.len());
+        Ok(dest)
     }
 
-    fn process_gpu(&self) {
+    // One submission for all of `src`, a whole number of blocks. Each block is copied in from a
+    // staging buffer and its output out to another, with the barriers vulkano puts between commands
+    // using the same buffers keeping the blocks in order, just as the semaphores do for `process`.
+    fn render_gpu(&mut self, src: &[f32], dest: &mut [f32]) -> Result<(), VocoderError> {
+        if self.state.lost {
+            return Err(VocoderError::DeviceLost);
+        }
+        for parity in 0..2 {
+            if self.state.late[parity] {
+                self.wait_gpu(parity, None)?;
+                self.state.late[parity] = false;
+            }
+        }
+        if self.state.in_flight.contains(&true) {
+            return Err(VocoderError::Busy);
+        }
+        // every earlier submission is done with
+        self.rendering = None;
+        // the settings stay fixed for the whole submission
+        self.write_settings(0);
+        self.write_settings(1);
+
+        // the clock and resyncs follow exactly what `submit` would do
+        let mut clock = self.clocks[0];
+        let mut resync_pending = self.resync_pending[0];
+        let settings = &self.settings[0];
+        let times = src.chunks(BLOCK_LENGTH).map(|_| {
+            let entry = time_entry(&clock, resync_pending || clock.resync_due(settings.resync_interval));
+            resync_pending = false;
+            clock.advance(BLOCK_LENGTH, settings);
+            entry
+        }).collect();
+        let staging = Staging::new(&self.context.memory_allocator, src, times)?;
+
+        let first = self.clocks[0].block;
+        let last = clock.block - 1;
         let mut builder = AutoCommandBufferBuilder::primary(
-            &self.command_buffer_allocator,
+            &self.context.command_buffer_allocator,
             self.queue.queue_family_index(),
             CommandBufferUsage::OneTimeSubmit,
-        ).unwrap();
+        ).map_err(setup_error)?;
+        for (index, block) in (first..=last).enumerate() {
+            let parity = (block % 2) as usize;
+            self.samplewise_fourier_descriptor_sets.record_upload(&mut builder, &staging, index, parity)?;
+            self.dispatches.record(&mut builder, parity, None);
+            self.samplewise_fourier_descriptor_sets.record_readback(&mut builder, &staging, index, parity)?;
+        }
+        let command_buffer = builder.build().map_err(setup_error)?;
 
-        builder
-            .bind_pipeline_compute(self.pipeline_ft.clone())
-            .bind_descriptor_sets(
-                PipelineBindPoint::Compute,
-                self.pipeline_ft.layout().clone(),
-                0,
-                self.descriptor_sets_ft.clone(),
-            )
-            .dispatch([1, 1, 1]).expect("failed to dispatch")
-            .bind_pipeline_compute(self.pipeline_vocoder.clone())
-            .bind_descriptor_sets(
-                PipelineBindPoint::Compute,
-                self.pipeline_vocoder.layout().clone(),
-                0,
-                self.descriptor_sets_vocoder.clone(),
-            )
-            .dispatch([1024, 1, 1]).expect("failed to dispatch");
-        let command_buffer = builder.build().unwrap();
-    
+        let parity = (last % 2) as usize;
+        self.submit_gpu(command_buffer.handle(), first, last)?;
+        self.state.in_flight[parity] = true;
+        if let Err(e) = self.wait_gpu(parity, None) {
+            // unless the device is gone, the batch may still be running: collect it as a late
+            // block, keeping what it uses alive until then
+            if self.state.in_flight[parity] && !self.state.lost {
+                self.state.late[parity] = true;
+                self.rendering = Some((command_buffer, staging));
+            }
+            return Err(e);
+        }
+        staging.read_results(dest);
+        // as `process` does per block, so a batch after a failed one fades back in
+        for (src, dest) in src.chunks(BLOCK_LENGTH).zip(dest.chunks_mut(BLOCK_LENGTH)) {
+            self.fallback.on_time(src, dest);
+        }
+        self.clocks[0] = clock;
+        self.resync_pending[0] = false;
+        Ok(())
+    }
+
+    /// Uploads a block of `BLOCK_LENGTH` samples and starts processing it without waiting.
+    ///
+    /// Up to two blocks can be in flight; collect the ticket from two submissions ago before
+    /// submitting again, or get `VocoderError::Busy`. Submitting block N+1 before collecting block N lets the upload overlap
+    /// the GPU work, at the cost of one more block of latency (see `PipelinedVocoder`).
+    pub fn submit(&mut self, src: &[f32]) -> Result<Ticket, VocoderError> {
+        self.submit_streams(&[src])
+    }
+
+    // one block per stream
+    fn submit_streams(&mut self, src: &[&[f32]]) -> Result<Ticket, VocoderError> {
+        let parity = self.parity();
+        if self.state.lost {
+            return Err(VocoderError::DeviceLost);
+        }
+        if self.state.in_flight[parity] {
+            return Err(VocoderError::Busy);
+        }
+        let upload_started = Instant::now();
+        for (stream, src) in src.iter().enumerate() {
+            let clock = &self.clocks[stream];
+            let resync = self.resync_pending[stream] || clock.resync_due(self.settings[stream].resync_interval);
+            self.samplewise_fourier_descriptor_sets.update_input(parity, stream, &src[..BLOCK_LENGTH]);
+            self.samplewise_fourier_descriptor_sets.update_time(parity, stream, clock, resync);
+        }
+        self.write_settings(parity);
+        if let Some(timings) = &mut self.timings {
+            timings.upload.record(upload_started.elapsed());
+        }
+        let block = self.clocks[0].block;
+        self.submit_gpu(self.command_buffers[parity].handle(), block, block)?;
+        self.resync_pending.fill(false);
+        self.state.in_flight[parity] = true;
+        for (clock, settings) in self.clocks.iter_mut().zip(&self.settings) {
+            clock.advance(BLOCK_LENGTH, settings);
+        }
+        Ok(Ticket { block })
+    }
+
+    // every stream is at the same block
+    fn parity(&self) -> usize {
+        (self.clocks[0].block % 2) as usize
+    }
+
+    /// Whether the ticket's block has finished, so `collect` would not block.
+    pub fn poll(&mut self, ticket: &Ticket) -> Result<bool, VocoderError> {
+        self.finished((ticket.block % 2) as usize)
+    }
+
+    /// Waits for the ticket's block and copies its `BLOCK_LENGTH` output samples to `dest`.
+    pub fn collect(&mut self, ticket: Ticket, dest: &mut [f32]) -> Result<(), VocoderError> {
+        self.collect_streams(ticket, &mut [dest])
+    }
+
+    fn collect_streams(&mut self, ticket: Ticket, dest: &mut [&mut [f32]]) -> Result<(), VocoderError> {
+        let parity = (ticket.block % 2) as usize;
+        if self.state.lost {
+            return Err(VocoderError::DeviceLost);
+        }
+        // a ticket from before `recover`, or its parity's slot was already taken back
+        assert!(self.state.in_flight[parity], "collected a block that is not in flight");
+        self.wait_gpu(parity, None)?;
+        let readback_started = Instant::now();
+        for (stream, dest) in dest.iter_mut().enumerate() {
+            self.samplewise_fourier_descriptor_sets.read_result(parity, stream, &mut dest[..BLOCK_LENGTH]);
+        }
+        if let Some(timings) = &mut self.timings {
+            timings.readback.record(readback_started.elapsed());
+        }
+        Ok(())
+    }
+
+    /// Times the device was lost since the vocoder was created.
+    pub fn device_losses(&self) -> u64 {
+        self.state.losses
+    }
+    /// Whether the device was lost and not recovered yet; `process` plays the fallback meanwhile.
+    pub fn is_lost(&self) -> bool {
+        self.state.lost
+    }
+
+    pub fn context(&self) -> &Arc<VocoderContext> {
+        &self.context
+    }
+
+    /// Moves to a new device after `VocoderError::DeviceLost`, from `VocoderContext::recover`.
+    /// Settings and deadline carry over; the stream itself restarts, which `process` covers with
+    /// the fallback signal. The new device gets a context of its own: vocoders sharing a context
+    /// should recover it once and move over with `recover_on` instead. Blocks while the device is
+    /// set up, so call it off the audio thread, as `maintain` does.
+    pub fn recover(&mut self) -> Result<(), VocoderError> {
+        match self.context.recover() {
+            Ok(context) => self.recover_on(&context),
+            Err(error) => {
+                self.state.recovery_failed(Instant::now());
+                Err(error)
+            }
+        }
+    }
+
+    /// Rebuilds the vocoder from `context` like `recover` does, e.g. on a device the host picked.
+    /// On failure the vocoder stays as it was, and `maintain` waits a while before retrying.
+    pub fn recover_on(&mut self, context: &Arc<VocoderContext>) -> Result<(), VocoderError> {
+        let mut vocoder = match Vocoder::with_streams(context, self.settings.clone(), self.analysis_length) {
+            Ok(vocoder) => vocoder,
+            Err(error) => {
+                self.state.recovery_failed(Instant::now());
+                return Err(error);
+            }
+        };
+        self.take_over(&mut vocoder);
+        Ok(())
+    }
 
-        let future = sync::now(self.vulkan_device.clone())
-            .then_execute(self.queue.clone(), command_buffer).unwrap()
-            .then_signal_fence_and_flush().unwrap();
-    
-        future.wait(None).unwrap();
+    /// Swaps in `successor`, e.g. built off the audio thread on a recovered context, carrying the
+    /// deadline, device losses, stats and fallback over as `recover_on` does; `successor` keeps
+    /// its own settings. The replaced vocoder is left in `successor`, to be dropped off the audio
+    /// thread as well, since that waits for its blocks in flight. Does not allocate.
+    pub fn take_over(&mut self, successor: &mut Vocoder) {
+        successor.state = DeviceState::new(self.state.losses);
+        successor.deadline = self.deadline;
+        successor.deadline_misses = self.deadline_misses;
+        successor.timings = self.timings.take();
+        std::mem::swap(&mut successor.fallback, &mut self.fallback);
+        std::mem::swap(self, successor);
+    }
+
+    fn check(&mut self, result: ash::vk::Result) -> Result<(), VocoderError> {
+        self.state.check(result)
+    }
+
+    fn finished(&mut self, parity: usize) -> Result<bool, VocoderError> {
+        let fence = self.fences[parity].handle();
+        let fns = self.vulkan_device.fns();
+        match unsafe { (fns.v1_0.get_fence_status)(self.vulkan_device.handle(), fence) } {
+            ash::vk::Result::NOT_READY => Ok(false),
+            result => self.check(result).map(|()| true),
+        }
+    }
+
+    // The GPU state stays consistent through a miss, as the late block still runs in order;
+    // only its output is lost.
+    fn retire_late(&mut self) -> Result<(), VocoderError> {
+        for parity in 0..2 {
+            if self.state.late[parity] && self.finished(parity)? {
+                self.wait_gpu(parity, None)?;
+                self.state.late[parity] = false;
+            }
+        }
+        Ok(())
+    }
+
+    // Submits the commands for blocks `first` to `last`, after those of the previous block if any.
+    // They signal the last block's fence and semaphore.
+    // Calls Vulkan directly, as vulkano's submission path allocates, and this runs on the audio thread.
+    fn submit_gpu(&mut self, command_buffer: ash::vk::CommandBuffer, first: u64, last: u64) -> Result<(), VocoderError> {
+        let parity = (last % 2) as usize;
+        let signal = self.semaphores[parity].handle();
+        let wait = self.semaphores[1 - (first % 2) as usize].handle();
+        let wait_stage = ash::vk::PipelineStageFlags::COMPUTE_SHADER;
+        let submit_info = ash::vk::SubmitInfo {
+            wait_semaphore_count: (first > 0) as u32,
+            p_wait_semaphores: &wait,
+            p_wait_dst_stage_mask: &wait_stage,
+            command_buffer_count: 1,
+            p_command_buffers: &command_buffer,
+            signal_semaphore_count: 1,
+            p_signal_semaphores: &signal,
+            ..Default::default()
+        };
+        let fns = self.vulkan_device.fns();
+        self.submitted_at[parity] = Instant::now();
+        let result = unsafe {
+            self.queue.with(|_| {
+                (fns.v1_0.queue_submit)(self.queue.handle(), 1, &submit_info, self.fences[parity].handle())
+            })
+        };
+        self.check(result)
+    }
+
+    // Whether the parity's block finished within `timeout`; it stays in flight if not.
+    fn wait_gpu(&mut self, parity: usize, timeout: Option<Duration>) -> Result<bool, VocoderError> {
+        let fence = self.fences[parity].handle();
+        let timeout = timeout.map_or(u64::MAX, |timeout| timeout.as_nanos().min(u64::MAX as u128) as u64);
+        let device = self.vulkan_device.clone();
+        let fns = device.fns();
+        let result = unsafe { (fns.v1_0.wait_for_fences)(device.handle(), 1, &fence, ash::vk::TRUE, timeout) };
+        if result == ash::vk::Result::TIMEOUT {
+            return Ok(false);
+        }
+        self.check(result)?;
+        let result = unsafe { (fns.v1_0.reset_fences)(device.handle(), 1, &fence) };
+        self.check(result)?;
+        self.state.in_flight[parity] = false;
+        if self.timings.is_some() {
+            let dispatch_times = match &self.timestamps {
+                Some(timestamps) => timestamps.dispatch_times(parity),
+                None => Ok(None),
+            };
+            if let Err(result) = dispatch_times {
+                self.check(result)?;
+            }
+            if let Some(timings) = &mut self.timings {
+                // as seen from here: a late block is only noticed on the next call
+                timings.submit_to_fence.record(self.submitted_at[parity].elapsed());
+                if let Ok(Some([vocoder, resolve])) = dispatch_times {
+                    timings.vocoder_dispatch.record(vocoder);
+                    timings.resolve_dispatch.record(resolve);
+                }
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl Drop for Vocoder {
+    // the device must be done with the buffers before they are freed
+    fn drop(&mut self) {
+        for parity in 0..2 {
+            if self.state.in_flight[parity] {
+                let _ = self.wait_gpu(parity, None);
+            }
+        }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_common as common;
+
+    // `length` samples through a vocoder made from `context`, block by block
+    fn render_on(context: &Arc<VocoderContext>, settings: &VocoderSettings, length: usize) -> Vec<f32> {
+        let mut vocoder = Vocoder::with_context(context, settings.clone(), DEFAULT_ANALYSIS_LENGTH);
+        common::process_blockwise(&mut vocoder, &common::sine(length), |_, _| {})
+    }
+
+    #[test]
+    fn unsupported_streams_are_rejected() {
+        let settings = [VocoderSettings::default()];
+        assert_eq!(check_streams(&settings, DEFAULT_ANALYSIS_LENGTH), Ok(()));
+        assert_eq!(check_streams(&settings, MIN_ANALYSIS_LENGTH), Ok(()));
+        assert_eq!(check_streams(&settings, MAX_ANALYSIS_LENGTH), Ok(()));
+        for analysis_length in [0, 1000, MIN_ANALYSIS_LENGTH / 2, MAX_ANALYSIS_LENGTH * 2] {
+            assert!(matches!(check_streams(&settings, analysis_length), Err(VocoderError::InvalidArgument(_))));
+        }
+        assert!(matches!(check_streams(&[], DEFAULT_ANALYSIS_LENGTH), Err(VocoderError::InvalidArgument(_))));
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn bin_reductions_agree() {
+        use context::{subgroup_reduction_supported, workgroup_size, BinReduction};
+        let (device, queues) = crate::vulcan_helper::create_vulcan_device();
+        let queues: Vec<_> = queues.collect();
+        let properties = device.physical_device().properties();
+        let device_workgroup_size = workgroup_size(
+            properties.max_compute_work_group_size[0], properties.max_compute_work_group_invocations,
+            properties.max_compute_shared_memory_size, DEFAULT_ANALYSIS_LENGTH,
+        );
+        let settings = VocoderSettings { pitch_shift_ratio: 1.3, resync_interval: 5, ..VocoderSettings::default() };
+        let length = 40 * BLOCK_LENGTH;
+        let render = |reduction, workgroup_size| {
+            let context = VocoderContext::with_forced_pipelines(device.clone(), queues.clone(), reduction, workgroup_size);
+            render_on(&context, &settings, length)
+        };
+        let expected = render(BinReduction::SharedMemory, device_workgroup_size);
+        assert!(expected.iter().any(|&s| s != 0.0));
+        let mut variants = vec![(BinReduction::SharedMemory, SAMPLE_CHUNKS * 16)];
+        if subgroup_reduction_supported(&device) {
+            let subgroup_size = properties.subgroup_size.unwrap() as usize;
+            variants.push((BinReduction::Subgroup, device_workgroup_size));
+            // tiles as wide as a subgroup take the subgroup path; half as wide, the shader's
+            // `linear_subgroups` fallback to shared memory
+            for tile_bins in [subgroup_size, subgroup_size / 2] {
+                let size = SAMPLE_CHUNKS * tile_bins;
+                if tile_bins >= 1 && size <= device_workgroup_size {
+                    variants.push((BinReduction::Subgroup, size));
+                }
+            }
+        }
+        for (reduction, size) in variants {
+            let output = render(reduction, size);
+            let error = output.iter().zip(&expected).map(|(a, b)| (a - b).abs()).fold(0.0f32, f32::max);
+            assert!(error < 1e-4, "{:?} with {} invocations is off by {}", reduction, size, error);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn recovery_carries_the_deadline_and_fade_over() {
+        let context = common::context();
+        let settings = VocoderSettings { pitch_shift_ratio: 1.3, ..VocoderSettings::default() };
+        let src = common::sine(BLOCK_LENGTH);
+        let mut dest = vec![0.0f32; BLOCK_LENGTH];
+        let mut vocoder = Vocoder::with_context(&context, settings.clone(), DEFAULT_ANALYSIS_LENGTH);
+        vocoder.set_deadline(Some(Duration::from_secs(1)));
+        for _ in 0..3 {
+            vocoder.process(&src, &mut dest);
+        }
+
+        // what a driver reset looks like from here, with nothing actually in flight
+        assert_eq!(vocoder.check(ash::vk::Result::ERROR_DEVICE_LOST), Err(VocoderError::DeviceLost));
+        assert!(vocoder.is_lost());
+        for _ in 0..2 {
+            assert_eq!(vocoder.try_process(&src, &mut dest), Err(VocoderError::DeviceLost));
+        }
+        assert_eq!(dest, src, "a lost device plays the dry input");
+        vocoder.recover_on(&context).unwrap();
+        assert!(!vocoder.is_lost());
+        assert_eq!((vocoder.device_losses(), vocoder.deadline()), (1, Some(Duration::from_secs(1))));
+
+        // the stream restarts, fading in from the dry input over 256 samples rather than jumping in
+        let mut fresh = vec![0.0f32; BLOCK_LENGTH];
+        Vocoder::with_context(&context, settings, DEFAULT_ANALYSIS_LENGTH).process(&src, &mut fresh);
+        vocoder.process(&src, &mut dest);
+        assert!((dest[0] - src[0]).abs() < 0.01);
+        assert_eq!(dest[256..], fresh[256..]);
+    }
+
+    #[test]
+    fn lost_device_is_told_apart() {
+        assert_eq!(VocoderError::from(ash::vk::Result::ERROR_DEVICE_LOST), VocoderError::DeviceLost);
+        assert_eq!(
+            VocoderError::from(ash::vk::Result::ERROR_OUT_OF_DEVICE_MEMORY),
+            VocoderError::Vulkan(VulkanError::OutOfDeviceMemory),
+        );
+    }
+}
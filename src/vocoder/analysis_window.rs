@@ -0,0 +1,108 @@
+use std::{
+    sync::Arc,
+};
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    descriptor_set::{
+        allocator::DescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet, layout::DescriptorSetLayout,
+    },
+    memory::allocator::{MemoryAllocator},
+};
+
+use super::{setup_error, AnalysisWindow, VocoderError};
+
+
+pub struct AnalysisWindowDescriptorSets<A: DescriptorSetAllocator + ?Sized> {
+    pub descriptor_sets: [Arc<PersistentDescriptorSet<A::Alloc>>; 2],
+    // one entry per stream, per block parity so a block in flight keeps the settings it was submitted with
+    buffers: [Arc<CpuAccessibleBuffer<[[f32; 4]]>>; 2],
+    analysis_length: usize,
+}
+
+impl<A: DescriptorSetAllocator + ?Sized> AnalysisWindowDescriptorSets<A> {
+    pub fn new(
+        window: AnalysisWindow,
+        analysis_length: usize,
+        streams: usize,
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        descriptor_set_allocator: &A,
+        set_layout: Arc<DescriptorSetLayout>,
+    ) -> Result<AnalysisWindowDescriptorSets<A>, VocoderError> {
+        let buffer = || {
+            let data = Self::buffer_data(window, analysis_length);
+            CpuAccessibleBuffer::from_iter(
+                memory_allocator, BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, false,
+                (0..streams).map(|_| data),
+            ).map_err(setup_error)
+        };
+        let buffers = [buffer()?, buffer()?];
+
+        let set = |buffer| PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            set_layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, buffer),
+            ],
+        ).map_err(setup_error);
+        let sets = [set(buffers[0].clone())?, set(buffers[1].clone())?];
+
+        Ok(AnalysisWindowDescriptorSets {
+            descriptor_sets: sets,
+            buffers,
+            analysis_length,
+        })
+    }
+    pub fn update(&mut self, parity: usize, stream: usize, window: AnalysisWindow) {
+        self.buffers[parity].write().unwrap()[stream] = Self::buffer_data(window, self.analysis_length);
+    }
+
+    // `a0 - a1 cos(x) + a2 cos(2x)` over the window, times `exp(-decay * age)` with age in samples
+    fn buffer_data(window: AnalysisWindow, analysis_length: usize) -> [f32; 4] {
+        match window {
+            AnalysisWindow::Rectangular => [1.0, 0.0, 0.0, 0.0],
+            AnalysisWindow::Hann => [0.5, 0.5, 0.0, 0.0],
+            AnalysisWindow::Hamming => [0.54, 0.46, 0.0, 0.0],
+            AnalysisWindow::Blackman => [0.42, 0.5, 0.08, 0.0],
+            // time constant of a quarter window, so the truncated tail is under 2%
+            AnalysisWindow::Exponential => [1.0, 0.0, 0.0, 4.0 / analysis_length as f32],
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_common::queue;
+    use crate::vocoder::{AnalysisWindow, Vocoder, VocoderSettings, BLOCK_LENGTH};
+
+    // root mean square over the settled second half
+    fn settled_level(samples: &[f32]) -> f32 {
+        let tail = &samples[samples.len() / 2..];
+        (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn windows_keep_the_output_level() {
+        let queue = queue();
+        let length = 48 * BLOCK_LENGTH;
+        let src: Vec<f32> = (0..length).map(|i| (i as f32 * 0.131).sin() * 0.5).collect();
+        let input_level = settled_level(&src);
+        // the pitch shift sweeps both taps across the window, through its tapered edges
+        let render = |analysis_window| {
+            let settings = VocoderSettings { pitch_shift_ratio: 1.25, analysis_window, ..VocoderSettings::default() };
+            Vocoder::new(queue.clone(), settings).render(&src).unwrap()
+        };
+        let rectangular = settled_level(&render(AnalysisWindow::Rectangular));
+        assert!(rectangular > input_level * 0.5, "rectangular window output at {}", rectangular / input_level);
+        for window in [AnalysisWindow::Hann, AnalysisWindow::Hamming, AnalysisWindow::Blackman, AnalysisWindow::Exponential] {
+            let output = render(window);
+            // within 3 dB of the rectangular window, and no peaks from a runaway correction
+            let level = settled_level(&output) / rectangular;
+            assert!((0.7..1.4).contains(&level), "{:?} output at {} of the rectangular window", window, level);
+            let peak = output.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+            assert!(peak < 4.0 * 0.5, "{:?} peaks at {}", window, peak);
+        }
+    }
+}
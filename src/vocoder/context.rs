@@ -0,0 +1,310 @@
+use std::{
+    collections::HashMap,
+    io,
+    iter,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use vulkano::{
+    command_buffer::allocator::StandardCommandBufferAllocator,
+    descriptor_set::allocator::StandardDescriptorSetAllocator,
+    device::{Device, Queue},
+    memory::allocator::StandardMemoryAllocator,
+    pipeline::{cache::PipelineCache, ComputePipeline},
+    shader::{ShaderModule, SpecializationConstants},
+    Version, VulkanObject,
+};
+
+use super::{setup_error, VocoderError, BLOCK_LENGTH, SAMPLE_CHUNKS};
+use super::pipeline_cache::{self, CacheKey};
+use crate::vulcan_helper::{enumerate_compute_devices, try_create_vulcan_device_on};
+
+// upper bound on invocations per workgroup, further limited by the device
+const MAX_WORKGROUP_SIZE: usize = 1024;
+
+
+/// Device, queues, allocators and compiled pipelines shared by any number of `Vocoder`s,
+/// e.g. one per participant of a call. A vocoder made from a context only allocates its own
+/// buffers and records its commands; pipelines are compiled once per analysis length.
+pub struct VocoderContext {
+    device: Arc<Device>,
+    queues: Vec<Arc<Queue>>,
+    next_queue: AtomicUsize,
+    pub(super) memory_allocator: StandardMemoryAllocator,
+    pub(super) descriptor_set_allocator: StandardDescriptorSetAllocator,
+    pub(super) command_buffer_allocator: StandardCommandBufferAllocator,
+    pipelines: Mutex<HashMap<usize, Pipelines>>,
+    pipeline_cache: Option<PipelineCacheFile>,
+    // reduction and workgroup size to use instead of what suits the device, see `with_forced_pipelines`
+    forced: Option<(BinReduction, usize)>,
+}
+
+/// How the vocoder pass sums its output over bins.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum BinReduction {
+    SharedMemory,
+    /// Subgroup arithmetic, falling back to shared memory within the shader wherever the
+    /// subgroups do not line up with the tiles.
+    Subgroup,
+}
+
+// a driver pipeline cache loaded from `path`, and written back there as pipelines are added
+struct PipelineCacheFile {
+    cache: Arc<PipelineCache>,
+    path: PathBuf,
+    key: CacheKey,
+}
+
+#[derive(Clone)]
+pub(super) struct Pipelines {
+    pub workgroup_size: usize,
+    pub vocoder: Arc<ComputePipeline>,
+    pub resolve: Arc<ComputePipeline>,
+}
+
+impl VocoderContext {
+    /// Vocoders made from the context take turns on `queues`, which must come from `device`.
+    pub fn new(device: Arc<Device>, queues: impl IntoIterator<Item = Arc<Queue>>) -> Arc<Self> {
+        Self::with_cache(device, queues, None, None)
+    }
+
+    /// Like `new`, but every pipeline uses `reduction` and `workgroup_size`, so the shader's paths
+    /// can be compared on one device. Forcing `Subgroup` needs `subgroup_reduction_supported`.
+    #[cfg(test)]
+    pub(super) fn with_forced_pipelines(
+        device: Arc<Device>, queues: impl IntoIterator<Item = Arc<Queue>>, reduction: BinReduction, workgroup_size: usize,
+    ) -> Arc<Self> {
+        Self::with_cache(device, queues, None, Some((reduction, workgroup_size)))
+    }
+
+    /// Like `new`, keeping compiled pipelines in the file at `path` so later runs skip the
+    /// driver's shader compilation. A missing file, or one written for another device or
+    /// driver version, is replaced once pipelines are compiled. Should the driver fail to create
+    /// the cache, the context compiles without one.
+    pub fn with_pipeline_cache(
+        device: Arc<Device>, queues: impl IntoIterator<Item = Arc<Queue>>, path: impl Into<PathBuf>,
+    ) -> Arc<Self> {
+        // like a file that cannot be written, a missing cache only costs compile time
+        let pipeline_cache = PipelineCacheFile::open(&device, path.into()).ok();
+        Self::with_cache(device, queues, pipeline_cache, None)
+    }
+
+    fn with_cache(
+        device: Arc<Device>, queues: impl IntoIterator<Item = Arc<Queue>>, pipeline_cache: Option<PipelineCacheFile>,
+        forced: Option<(BinReduction, usize)>,
+    ) -> Arc<Self> {
+        let queues: Vec<_> = queues.into_iter().collect();
+        assert!(!queues.is_empty(), "a vocoder context needs at least one queue");
+        Arc::new(VocoderContext {
+            memory_allocator: StandardMemoryAllocator::new_default(device.clone()),
+            descriptor_set_allocator: StandardDescriptorSetAllocator::new(device.clone()),
+            command_buffer_allocator: StandardCommandBufferAllocator::new(device.clone(), Default::default()),
+            device,
+            queues,
+            next_queue: AtomicUsize::new(0),
+            pipelines: Mutex::new(HashMap::new()),
+            pipeline_cache,
+            forced,
+        })
+    }
+
+    pub fn device(&self) -> &Arc<Device> {
+        &self.device
+    }
+
+    /// A context like this one on a new device, after `VocoderError::DeviceLost`: the same
+    /// physical device if it comes back, otherwise the first other one able to run the vocoder.
+    /// Its vocoders move over one by one with `Vocoder::recover_on`. Pipelines are compiled
+    /// again, from the same pipeline cache file if there is one.
+    pub fn recover(&self) -> Result<Arc<Self>, VocoderError> {
+        let physical_device = self.device.physical_device();
+        let others = enumerate_compute_devices(physical_device.instance())
+            .into_iter()
+            .filter(|(other, _)| other.handle() != physical_device.handle());
+        let candidates = iter::once((physical_device.clone(), self.queues[0].queue_family_index())).chain(others);
+        for (physical_device, queue_family_index) in candidates {
+            if let Ok((device, queues)) = try_create_vulcan_device_on(physical_device, queue_family_index) {
+                // without its cache, the new context only compiles from scratch
+                let pipeline_cache = self.pipeline_cache.as_ref()
+                    .and_then(|file| PipelineCacheFile::open(&device, file.path.clone()).ok());
+                return Ok(Self::with_cache(device, queues, pipeline_cache, self.forced));
+            }
+        }
+        Err(VocoderError::NoDevice)
+    }
+
+    /// Compiles the pipelines for `analysis_length` now rather than on the first vocoder using it.
+    pub fn prepare(&self, analysis_length: usize) -> Result<(), VocoderError> {
+        self.pipelines(analysis_length).map(|_| ())
+    }
+
+    pub(super) fn next_queue(&self) -> Arc<Queue> {
+        let index = self.next_queue.fetch_add(1, Ordering::Relaxed) % self.queues.len();
+        self.queues[index].clone()
+    }
+
+    /// Writes the pipeline cache back to its file; does nothing without one.
+    /// Happens by itself whenever new pipelines are compiled.
+    pub fn save_pipeline_cache(&self) -> io::Result<()> {
+        match &self.pipeline_cache {
+            Some(file) => pipeline_cache::save(&file.key, &file.path, &file.cache.get_data().map_err(io::Error::other)?),
+            None => Ok(()),
+        }
+    }
+
+    pub(super) fn pipelines(&self, analysis_length: usize) -> Result<Pipelines, VocoderError> {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        if let Some(existing) = pipelines.get(&analysis_length) {
+            return Ok(existing.clone());
+        }
+        let (reduction, workgroup_size) = self.forced.unwrap_or_else(|| {
+            let limits = self.device.physical_device().properties();
+            let workgroup_size = workgroup_size(
+                limits.max_compute_work_group_size[0], limits.max_compute_work_group_invocations,
+                limits.max_compute_shared_memory_size, analysis_length,
+            );
+            let reduction = match subgroup_reduction_supported(&self.device) {
+                true => BinReduction::Subgroup,
+                false => BinReduction::SharedMemory,
+            };
+            (reduction, workgroup_size)
+        });
+        let (vocoder, resolve) = create_pipelines(
+            &self.device, analysis_length, workgroup_size, reduction,
+            self.pipeline_cache.as_ref().map(|file| &file.cache),
+        )?;
+        let created = Pipelines { workgroup_size, vocoder, resolve };
+        pipelines.insert(analysis_length, created.clone());
+        // a cache that cannot be written only costs the next run its head start
+        let _ = self.save_pipeline_cache();
+        Ok(created)
+    }
+}
+
+impl PipelineCacheFile {
+    fn open(device: &Arc<Device>, path: PathBuf) -> Result<PipelineCacheFile, VocoderError> {
+        let key = CacheKey::of(device);
+        let cache = match pipeline_cache::load(&key, &path) {
+            // the header matched this device and driver, which then validates the data itself
+            Some(data) => unsafe { PipelineCache::with_data(device.clone(), &data) },
+            None => PipelineCache::empty(device.clone()),
+        }.map_err(setup_error)?;
+        Ok(PipelineCacheFile { cache, path, key })
+    }
+}
+
+fn create_pipelines(
+    device: &Arc<Device>, analysis_length: usize, workgroup_size: usize, reduction: BinReduction,
+    cache: Option<&Arc<PipelineCache>>,
+) -> Result<(Arc<ComputePipeline>, Arc<ComputePipeline>), VocoderError> {
+    let workgroup_size = workgroup_size as i32;
+    let analysis_length = analysis_length as i32;
+    let pipeline_vocoder = {
+        mod cs {
+            vulkano_shaders::shader! {
+                ty: "compute",
+                path: "src/vocoder/vocoder.glsl.comp",
+            }
+        }
+        // same kernel, reducing over bins with subgroup arithmetic where the device has it
+        mod cs_subgroup {
+            vulkano_shaders::shader! {
+                ty: "compute",
+                path: "src/vocoder/vocoder.glsl.comp",
+                define: [("SUBGROUP_REDUCTION", "1")],
+                vulkan_version: "1.1",
+                spirv_version: "1.3",
+            }
+        }
+        if reduction == BinReduction::Subgroup {
+            let specialization_constants = cs_subgroup::SpecializationConstants {
+                ANALYSIS_LENGTH: analysis_length,
+                WORKGROUP_SIZE: workgroup_size,
+                SAMPLE_CHUNKS: SAMPLE_CHUNKS as i32,
+            };
+            compute_pipeline(device, cs_subgroup::load(device.clone()).map_err(setup_error)?, &specialization_constants, cache)
+        } else {
+            let specialization_constants = cs::SpecializationConstants {
+                ANALYSIS_LENGTH: analysis_length,
+                WORKGROUP_SIZE: workgroup_size,
+                SAMPLE_CHUNKS: SAMPLE_CHUNKS as i32,
+            };
+            compute_pipeline(device, cs::load(device.clone()).map_err(setup_error)?, &specialization_constants, cache)
+        }
+    }?;
+
+    let pipeline_resolve = {
+        mod cs {
+            vulkano_shaders::shader! {
+                ty: "compute",
+                path: "src/vocoder/resolve.glsl.comp",
+            }
+        }
+        let specialization_constants = cs::SpecializationConstants {
+            ANALYSIS_LENGTH: analysis_length,
+            WORKGROUP_SIZE: workgroup_size,
+            SAMPLE_CHUNKS: SAMPLE_CHUNKS as i32,
+        };
+        compute_pipeline(device, cs::load(device.clone()).map_err(setup_error)?, &specialization_constants, cache)
+    }?;
+
+    Ok((pipeline_vocoder, pipeline_resolve))
+}
+
+fn compute_pipeline(
+    device: &Arc<Device>, shader: Arc<ShaderModule>, specialization_constants: &impl SpecializationConstants,
+    cache: Option<&Arc<PipelineCache>>,
+) -> Result<Arc<ComputePipeline>, VocoderError> {
+    ComputePipeline::new(
+        device.clone(),
+        shader.entry_point("main").unwrap(),
+        specialization_constants, cache.cloned(), |_| {},
+    ).map_err(setup_error)
+}
+
+/// Whether the vocoder pass can sum over bins with subgroup arithmetic instead of shared memory alone.
+pub(super) fn subgroup_reduction_supported(device: &Device) -> bool {
+    let properties = device.physical_device().properties();
+    device.api_version() >= Version::V1_1
+        && properties.subgroup_supported_operations.is_some_and(|operations| operations.arithmetic)
+        && properties.subgroup_supported_stages.is_some_and(|stages| stages.compute)
+}
+
+/// Largest power of two that fits the device's workgroup limits, the analysis length and `MAX_WORKGROUP_SIZE`.
+pub(super) fn workgroup_size(max_size_x: u32, max_invocations: u32, max_shared_memory: u32, analysis_length: usize) -> usize {
+    // the vocoder pass shares the block's inputs and expiring samples, plus 12 bytes per invocation
+    let shared_limit = (max_shared_memory as usize).saturating_sub(8 * BLOCK_LENGTH) / 12;
+    let limit = (max_size_x.min(max_invocations) as usize)
+        .min(shared_limit)
+        .min(analysis_length)
+        .min(MAX_WORKGROUP_SIZE);
+    1 << limit.ilog2()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workgroup_size_fits_device_limits() {
+        assert_eq!(workgroup_size(1024, 1024, 32768, 1024), 1024);
+        assert_eq!(workgroup_size(65535, 1536, 49152, 4096), 1024);
+        assert_eq!(workgroup_size(1024, 256, 32768, 4096), 256);
+        assert_eq!(workgroup_size(384, 1024, 32768, 1024), 256);
+        assert_eq!(workgroup_size(1024, 1024, 32768, 256), 256);
+        assert_eq!(workgroup_size(128, 128, 16384, 256), 128);
+        // the minimum shared memory Vulkan guarantees
+        assert_eq!(workgroup_size(1024, 1024, 16384, 1024), 512);
+    }
+
+    #[test]
+    fn context_can_be_shared_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<VocoderContext>();
+    }
+}
@@ -3,44 +3,53 @@ use std::{
 };
 
 use vulkano::{
-    buffer::{BufferUsage, DeviceLocalBuffer},
+    buffer::{BufferUsage, CpuAccessibleBuffer},
     descriptor_set::{
         allocator::DescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet, layout::DescriptorSetLayout,
     },
-    memory::allocator::{MemoryAllocator}, command_buffer::AutoCommandBufferBuilder,
+    memory::allocator::{MemoryAllocator},
 };
 
+use super::{setup_error, VocoderError};
+
 
 pub struct EqualizerDescriptorSets<A: DescriptorSetAllocator + ?Sized> {
-    pub descriptor_set: Arc<PersistentDescriptorSet<A::Alloc>>,
+    pub descriptor_sets: [Arc<PersistentDescriptorSet<A::Alloc>>; 2],
+    // one entry per stream, per block parity so a block in flight keeps the settings it was submitted with
+    buffers: [Arc<CpuAccessibleBuffer<[[f32; 8]]>>; 2],
 }
 
 impl<A: DescriptorSetAllocator + ?Sized> EqualizerDescriptorSets<A> {
-    pub fn new<L>(
+    pub fn new(
         polynomial: [f32; 8],
+        streams: usize,
         memory_allocator: &(impl MemoryAllocator + ?Sized),
         descriptor_set_allocator: &A,
-        command_buffer_builder: &mut AutoCommandBufferBuilder<L>,
         set_layout: Arc<DescriptorSetLayout>,
-    ) -> EqualizerDescriptorSets<A>  {
-        let buffer = {
-            DeviceLocalBuffer::from_data(
-                memory_allocator,
-                polynomial,
-                BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, command_buffer_builder,
-            ).unwrap()
+    ) -> Result<EqualizerDescriptorSets<A>, VocoderError> {
+        let buffer = || {
+            CpuAccessibleBuffer::from_iter(
+                memory_allocator, BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, false,
+                (0..streams).map(|_| polynomial),
+            ).map_err(setup_error)
         };
+        let buffers = [buffer()?, buffer()?];
 
-        let set = PersistentDescriptorSet::new(
+        let set = |buffer| PersistentDescriptorSet::new(
             descriptor_set_allocator,
             set_layout.clone(),
             [
-                WriteDescriptorSet::buffer(0, buffer.clone()),
+                WriteDescriptorSet::buffer(0, buffer),
             ],
-        ).unwrap();
+        ).map_err(setup_error);
+        let sets = [set(buffers[0].clone())?, set(buffers[1].clone())?];
 
-        EqualizerDescriptorSets {
-            descriptor_set: set,
-        }
+        Ok(EqualizerDescriptorSets {
+            descriptor_sets: sets,
+            buffers,
+        })
+    }
+    pub fn update(&mut self, parity: usize, stream: usize, polynomial: [f32; 8]) {
+        self.buffers[parity].write().unwrap()[stream] = polynomial;
     }
 }
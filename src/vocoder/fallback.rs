@@ -0,0 +1,90 @@
+// samples over which the output moves between the vocoder and the dry input
+const FADE_LENGTH: usize = 256;
+
+/// Output for blocks the GPU did not finish in time. Fades from the last good block to the dry
+/// input on a miss, and back to the vocoder's output once blocks arrive on time again.
+/// The last good block is played backwards, so it starts where the output left off.
+pub struct Fallback {
+    last_good: Vec<f32>,
+    // 0 plays the vocoder's output, 1 the dry input
+    dry_mix: f32,
+}
+
+impl Fallback {
+    pub fn new(block_length: usize) -> Self {
+        Fallback { last_good: vec![0.0; block_length], dry_mix: 0.0 }
+    }
+
+    /// `dest` holds the vocoder's output for `src`; fades it back in if the dry input was playing.
+    pub fn on_time(&mut self, src: &[f32], dest: &mut [f32]) {
+        self.last_good.copy_from_slice(dest);
+        if self.dry_mix > 0.0 {
+            self.fade(src, dest, 0.0);
+        }
+    }
+
+    /// Fills `dest` for a block the GPU missed.
+    pub fn missed(&mut self, src: &[f32], dest: &mut [f32]) {
+        for (d, s) in dest.iter_mut().zip(self.last_good.iter().rev()) {
+            *d = *s;
+        }
+        self.fade(src, dest, 1.0);
+    }
+
+    fn fade(&mut self, src: &[f32], dest: &mut [f32], target: f32) {
+        let step = 1.0 / FADE_LENGTH as f32;
+        for (d, s) in dest.iter_mut().zip(src) {
+            self.dry_mix = if target > self.dry_mix {
+                (self.dry_mix + step).min(target)
+            } else {
+                (self.dry_mix - step).max(target)
+            };
+            *d = *d * (1.0 - self.dry_mix) + s * self.dry_mix;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misses_fade_to_dry_and_back() {
+        let dry = vec![0.5f32; 1024];
+        let wet = vec![-0.5f32; 1024];
+        let mut fallback = Fallback::new(1024);
+        let mut out = wet.clone();
+        fallback.on_time(&dry, &mut out);
+        assert_eq!(out, wet);
+
+        fallback.missed(&dry, &mut out);
+        assert!((out[0] - wet[1023]).abs() < 0.01);
+        assert_eq!(out[FADE_LENGTH..], dry[FADE_LENGTH..]);
+        fallback.missed(&dry, &mut out);
+        assert_eq!(out, dry);
+
+        let mut out = wet.clone();
+        fallback.on_time(&dry, &mut out);
+        assert!((out[0] - dry[0]).abs() < 0.01);
+        assert_eq!(out[FADE_LENGTH..], wet[FADE_LENGTH..]);
+    }
+
+    #[test]
+    fn fades_have_no_jumps() {
+        let mut fallback = Fallback::new(1024);
+        let mut stream = Vec::new();
+        for (block, on_time) in [true, false, false, true, false, true].into_iter().enumerate() {
+            let t0 = block * 1024;
+            let dry: Vec<f32> = (t0..t0 + 1024).map(|t| (t as f32 * 0.01).sin()).collect();
+            let mut out: Vec<f32> = (t0..t0 + 1024).map(|t| 0.5 * (t as f32 * 0.013).cos()).collect();
+            if on_time {
+                fallback.on_time(&dry, &mut out);
+            } else {
+                fallback.missed(&dry, &mut out);
+            }
+            stream.extend(out);
+        }
+        assert!(stream.windows(2).all(|w| (w[1] - w[0]).abs() < 0.02));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    sync::Arc,
+};
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    descriptor_set::{
+        allocator::DescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet, layout::DescriptorSetLayout,
+    },
+    memory::allocator::{MemoryAllocator},
+};
+
+use super::{setup_error, FrequencyShiftMode, VocoderError};
+
+
+pub struct FrequencyShiftDescriptorSets<A: DescriptorSetAllocator + ?Sized> {
+    pub descriptor_sets: [Arc<PersistentDescriptorSet<A::Alloc>>; 2],
+    // one entry per stream, per block parity so a block in flight keeps the settings it was submitted with
+    buffers: [Arc<CpuAccessibleBuffer<[[f32; 2]]>>; 2],
+}
+
+impl<A: DescriptorSetAllocator + ?Sized> FrequencyShiftDescriptorSets<A> {
+    pub fn new(
+        frequency_shift: f32,
+        mode: FrequencyShiftMode,
+        sample_rate: f32,
+        streams: usize,
+        memory_allocator: &(impl MemoryAllocator + ?Sized),
+        descriptor_set_allocator: &A,
+        set_layout: Arc<DescriptorSetLayout>,
+    ) -> Result<FrequencyShiftDescriptorSets<A>, VocoderError> {
+        let buffer = || {
+            let data = Self::buffer_data(frequency_shift, mode, sample_rate);
+            CpuAccessibleBuffer::from_iter(
+                memory_allocator, BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, false,
+                (0..streams).map(|_| data),
+            ).map_err(setup_error)
+        };
+        let buffers = [buffer()?, buffer()?];
+
+        let set = |buffer| PersistentDescriptorSet::new(
+            descriptor_set_allocator,
+            set_layout.clone(),
+            [
+                WriteDescriptorSet::buffer(0, buffer),
+            ],
+        ).map_err(setup_error);
+        let sets = [set(buffers[0].clone())?, set(buffers[1].clone())?];
+
+        Ok(FrequencyShiftDescriptorSets {
+            descriptor_sets: sets,
+            buffers,
+        })
+    }
+    pub fn update(&mut self, parity: usize, stream: usize, frequency_shift: f32, mode: FrequencyShiftMode, sample_rate: f32) {
+        self.buffers[parity].write().unwrap()[stream] = Self::buffer_data(frequency_shift, mode, sample_rate);
+    }
+
+    // shift in cycles per sample, mode as a mix factor for the shader
+    fn buffer_data(frequency_shift: f32, mode: FrequencyShiftMode, sample_rate: f32) -> [f32; 2] {
+        let ring_modulation = match mode {
+            FrequencyShiftMode::SingleSideband => 0.0f32,
+            FrequencyShiftMode::RingModulation => 1.0f32,
+        };
+        [frequency_shift / sample_rate, ring_modulation]
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+
+    use super::*;
+    use crate::test_common::{process_blockwise, queue};
+    use crate::vocoder::{Vocoder, VocoderSettings, BLOCK_LENGTH};
+
+    type Sets = FrequencyShiftDescriptorSets<StandardDescriptorSetAllocator>;
+
+    #[test]
+    fn buffer_holds_cycles_per_sample_and_the_mode() {
+        assert_eq!(Sets::buffer_data(480.0, FrequencyShiftMode::SingleSideband, 48000.0), [0.01, 0.0]);
+        assert_eq!(Sets::buffer_data(480.0, FrequencyShiftMode::RingModulation, 48000.0), [0.01, 1.0]);
+        // downward shifts keep their sign, the shader wraps the phase either way
+        assert_eq!(Sets::buffer_data(-441.0, FrequencyShiftMode::SingleSideband, 44100.0), [-0.01, 0.0]);
+        assert_eq!(Sets::buffer_data(0.0, FrequencyShiftMode::RingModulation, 44100.0), [0.0, 1.0]);
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn single_sideband_moves_a_sine_by_the_shift() {
+        let settings = VocoderSettings {
+            frequency_shift: 150.0,
+            frequency_shift_mode: FrequencyShiftMode::SingleSideband,
+            ..VocoderSettings::default()
+        };
+        let mut vocoder = Vocoder::new(queue(), settings.clone());
+        let sine = 1000.0;
+        let length = 64 * BLOCK_LENGTH;
+        let src: Vec<f32> = (0..length)
+            .map(|i| (std::f32::consts::TAU * sine * i as f32 / settings.sample_rate).sin() * 0.5)
+            .collect();
+        let dest = process_blockwise(&mut vocoder, &src, |_, _| {});
+
+        // Hann-windowed power at `frequency` over the settled second half
+        let tail = &dest[length / 2..];
+        let power = |frequency: f32| {
+            let (mut re, mut im) = (0.0f64, 0.0f64);
+            for (i, &s) in tail.iter().enumerate() {
+                let window = 0.5 - 0.5 * (std::f64::consts::TAU * i as f64 / tail.len() as f64).cos();
+                let phase = std::f64::consts::TAU * frequency as f64 * i as f64 / settings.sample_rate as f64;
+                re += s as f64 * window * phase.cos();
+                im -= s as f64 * window * phase.sin();
+            }
+            re * re + im * im
+        };
+        let peak = (0..=400)
+            .map(|step| 500.0 + step as f32 * 5.0)
+            .max_by(|a, b| power(*a).total_cmp(&power(*b)))
+            .unwrap();
+        assert!((peak - (sine + settings.frequency_shift)).abs() <= 5.0, "sine moved to {} Hz", peak);
+        // a single sideband leaves no image below the carrier
+        assert!(power(sine - settings.frequency_shift) < power(peak) * 1e-2);
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use vulkano::device::Device;
+
+
+// file layout: MAGIC, FORMAT_VERSION, the device's CacheKey, then the driver's cache data
+const MAGIC: &[u8; 4] = b"VVPC";
+const FORMAT_VERSION: u32 = 1;
+const HEADER_LENGTH: usize = 4 + 4 + 16 + 4 + 16;
+
+/// What a pipeline cache file was written for. The driver would reject data from another
+/// device or driver anyway; checking first keeps it from ever seeing such data.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CacheKey {
+    pub device_uuid: [u8; 16],
+    pub driver_version: u32,
+    pub pipeline_cache_uuid: [u8; 16],
+}
+
+impl CacheKey {
+    pub fn of(device: &Device) -> CacheKey {
+        let properties = device.physical_device().properties();
+        CacheKey {
+            // only reported from Vulkan 1.1 on; the cache UUID still tells drivers apart
+            device_uuid: properties.device_uuid.unwrap_or_default(),
+            driver_version: properties.driver_version,
+            pipeline_cache_uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    fn header(&self) -> [u8; HEADER_LENGTH] {
+        let mut header = [0; HEADER_LENGTH];
+        header[0..4].copy_from_slice(MAGIC);
+        header[4..8].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header[8..24].copy_from_slice(&self.device_uuid);
+        header[24..28].copy_from_slice(&self.driver_version.to_le_bytes());
+        header[28..44].copy_from_slice(&self.pipeline_cache_uuid);
+        header
+    }
+}
+
+pub fn encode(key: &CacheKey, data: &[u8]) -> Vec<u8> {
+    [&key.header()[..], data].concat()
+}
+
+/// The driver's data in `file`, if it was written for `key`.
+pub fn decode<'a>(key: &CacheKey, file: &'a [u8]) -> Option<&'a [u8]> {
+    file.strip_prefix(&key.header()[..])
+}
+
+/// Cache data for `key` from `path`, or `None` if the file is missing, unreadable or stale.
+pub fn load(key: &CacheKey, path: &Path) -> Option<Vec<u8>> {
+    let file = fs::read(path).ok()?;
+    decode(key, &file).map(|data| data.to_vec())
+}
+
+/// Replaces `path` as a whole, so concurrent readers never see half a file.
+pub fn save(key: &CacheKey, path: &Path, data: &[u8]) -> io::Result<()> {
+    // one name per save, as contexts in one process may share the file
+    static SAVES: AtomicU64 = AtomicU64::new(0);
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}.{}.tmp", std::process::id(), SAVES.fetch_add(1, Ordering::Relaxed)));
+    let temporary = PathBuf::from(temporary);
+    let written = create_new(&temporary)
+        .and_then(|mut file| file.write_all(&encode(key, data)))
+        .and_then(|()| fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+// Never opens whatever already sits at `path`, such as a link planted at the predictable name;
+// a leftover from a crashed process with the same id is removed, not followed, and made anew.
+fn create_new(path: &Path) -> io::Result<fs::File> {
+    let open = || OpenOptions::new().write(true).create_new(true).open(path);
+    match open() {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            fs::remove_file(path)?;
+            open()
+        }
+        result => result,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: CacheKey = CacheKey {
+        device_uuid: [1; 16],
+        driver_version: 42,
+        pipeline_cache_uuid: [2; 16],
+    };
+
+    #[test]
+    fn data_round_trips() {
+        let file = encode(&KEY, b"driver data");
+        assert_eq!(decode(&KEY, &file), Some(&b"driver data"[..]));
+    }
+
+    #[test]
+    fn stale_or_foreign_files_are_ignored() {
+        let file = encode(&KEY, b"driver data");
+        let updated_driver = CacheKey { driver_version: 43, ..KEY };
+        let other_device = CacheKey { device_uuid: [3; 16], ..KEY };
+        assert_eq!(decode(&updated_driver, &file), None);
+        assert_eq!(decode(&other_device, &file), None);
+        assert_eq!(decode(&KEY, &file[..HEADER_LENGTH - 1]), None);
+        assert_eq!(decode(&KEY, b"not a cache file at all, but long enough to hold a header"), None);
+    }
+
+    #[test]
+    fn saved_files_load_back() {
+        let path = std::env::temp_dir().join(format!("vocoder-volcano-cache-test-{}", std::process::id()));
+        save(&KEY, &path, b"driver data").unwrap();
+        assert_eq!(load(&KEY, &path), Some(b"driver data".to_vec()));
+        assert_eq!(load(&CacheKey { driver_version: 0, ..KEY }, &path), None);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(load(&KEY, &path), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn planted_links_are_not_followed() {
+        let victim = std::env::temp_dir().join(format!("vocoder-volcano-cache-victim-{}", std::process::id()));
+        fs::write(&victim, b"precious").unwrap();
+        let link = std::env::temp_dir().join(format!("vocoder-volcano-cache-link-{}", std::process::id()));
+        let _ = fs::remove_file(&link);
+        std::os::unix::fs::symlink(&victim, &link).unwrap();
+        create_new(&link).unwrap().write_all(b"driver data").unwrap();
+        assert_eq!(fs::read(&victim).unwrap(), b"precious");
+        assert_eq!(fs::read(&link).unwrap(), b"driver data");
+        fs::remove_file(&link).unwrap();
+        fs::remove_file(&victim).unwrap();
+    }
+}
@@ -3,57 +3,56 @@ use std::{
 };
 
 use vulkano::{
-    buffer::{BufferUsage, DeviceLocalBuffer},
+    buffer::{BufferUsage, CpuAccessibleBuffer},
     descriptor_set::{
         allocator::DescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet, layout::DescriptorSetLayout,
     },
-    memory::allocator::{MemoryAllocator}, command_buffer::AutoCommandBufferBuilder,
+    memory::allocator::{MemoryAllocator},
 };
 
+use super::{setup_error, VocoderError};
+
 
 pub struct PitchShiftDescriptorSets<A: DescriptorSetAllocator + ?Sized> {
-    pub descriptor_set_ft: Arc<PersistentDescriptorSet<A::Alloc>>,
-    pub descriptor_set_ift: Arc<PersistentDescriptorSet<A::Alloc>>,
+    pub descriptor_sets: [Arc<PersistentDescriptorSet<A::Alloc>>; 2],
+    // one entry per stream, per block parity so a block in flight keeps the settings it was submitted with
+    buffers: [Arc<CpuAccessibleBuffer<[[f32; 3]]>>; 2],
 }
 
 impl<A: DescriptorSetAllocator + ?Sized> PitchShiftDescriptorSets<A> {
-    pub fn new<L>(
+    pub fn new(
         pitch_ratio: f32,
         delay: f32,
         mix_span: f32,
+        streams: usize,
         memory_allocator: &(impl MemoryAllocator + ?Sized),
         descriptor_set_allocator: &A,
-        command_buffer_builder: &mut AutoCommandBufferBuilder<L>,
-        set_layout_ft: Arc<DescriptorSetLayout>,
-        set_layout_ift: Arc<DescriptorSetLayout>,
-    ) -> PitchShiftDescriptorSets<A>  {
-        let buffer = {
-            DeviceLocalBuffer::from_data(
-                memory_allocator,
-                [pitch_ratio, delay, mix_span],
-                BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, command_buffer_builder,
-            ).unwrap()
+        set_layout: Arc<DescriptorSetLayout>,
+    ) -> Result<PitchShiftDescriptorSets<A>, VocoderError> {
+        let buffer = || {
+            let data = [pitch_ratio, delay, mix_span];
+            CpuAccessibleBuffer::from_iter(
+                memory_allocator, BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, false,
+                (0..streams).map(|_| data),
+            ).map_err(setup_error)
         };
+        let buffers = [buffer()?, buffer()?];
     
-        let set_ft = PersistentDescriptorSet::new(
-            descriptor_set_allocator,
-            set_layout_ft.clone(),
-            [
-                WriteDescriptorSet::buffer(0, buffer.clone()),
-            ],
-        ).unwrap();
-
-        let set_ift = PersistentDescriptorSet::new(
+        let set = |buffer| PersistentDescriptorSet::new(
             descriptor_set_allocator,
-            set_layout_ift.clone(),
+            set_layout.clone(),
             [
-                WriteDescriptorSet::buffer(0, buffer.clone()),
+                WriteDescriptorSet::buffer(0, buffer),
             ],
-        ).unwrap();
+        ).map_err(setup_error);
+        let sets = [set(buffers[0].clone())?, set(buffers[1].clone())?];
 
-        PitchShiftDescriptorSets {
-            descriptor_set_ft: set_ft,
-            descriptor_set_ift: set_ift,
-        }
+        Ok(PitchShiftDescriptorSets {
+            descriptor_sets: sets,
+            buffers,
+        })
+    }
+    pub fn update(&mut self, parity: usize, stream: usize, pitch_ratio: f32, delay: f32, mix_span: f32) {
+        self.buffers[parity].write().unwrap()[stream] = [pitch_ratio, delay, mix_span];
     }
 }
@@ -0,0 +1,119 @@
+use std::time::{Duration, Instant};
+
+use super::VocoderError;
+
+
+// wait before the first retry after a failed recovery, doubled with every further failure
+const FIRST_RETRY: Duration = Duration::from_millis(250);
+const LAST_RETRY: Duration = Duration::from_secs(8);
+
+/// What a `Vocoder` knows about its device apart from the device itself: the blocks in flight,
+/// whether the device was lost, and when replacing it may be tried next. Kept free of Vulkan
+/// objects so the bookkeeping can be tested without a GPU.
+pub(super) struct DeviceState {
+    // parities whose block was submitted but not collected yet
+    pub in_flight: [bool; 2],
+    // in-flight blocks `process` gave up on; collected and discarded once they finish
+    pub late: [bool; 2],
+    // set from the first `VocoderError::DeviceLost` until a recovery succeeds
+    pub lost: bool,
+    pub losses: u64,
+    failed_recoveries: u32,
+    next_recovery: Option<Instant>,
+}
+
+impl DeviceState {
+    /// A fresh device, after `losses` earlier ones were lost.
+    pub fn new(losses: u64) -> Self {
+        DeviceState {
+            in_flight: [false; 2],
+            late: [false; 2],
+            lost: false,
+            losses,
+            failed_recoveries: 0,
+            next_recovery: None,
+        }
+    }
+
+    /// Records a lost device, of which nothing in flight will ever finish, and turns the result
+    /// into an error.
+    pub fn check(&mut self, result: ash::vk::Result) -> Result<(), VocoderError> {
+        if result == ash::vk::Result::ERROR_DEVICE_LOST && !self.lost {
+            self.lost = true;
+            self.losses += 1;
+            self.in_flight = [false; 2];
+            self.late = [false; 2];
+        }
+        result.result().map_err(VocoderError::from)
+    }
+
+    /// Whether the device is lost and the wait after the last failed recovery, if any, is over.
+    pub fn recovery_due(&self, now: Instant) -> bool {
+        self.lost && self.next_recovery.is_none_or(|next| now >= next)
+    }
+
+    pub fn recovery_failed(&mut self, now: Instant) {
+        self.next_recovery = Some(now + retry_delay(self.failed_recoveries));
+        self.failed_recoveries = self.failed_recoveries.saturating_add(1);
+    }
+}
+
+fn retry_delay(failed_recoveries: u32) -> Duration {
+    FIRST_RETRY.saturating_mul(1 << failed_recoveries.min(16)).min(LAST_RETRY)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_loss_is_counted_once_and_drops_blocks_in_flight() {
+        let mut state = DeviceState::new(2);
+        state.in_flight = [true, true];
+        state.late = [true, false];
+        assert_eq!(state.check(ash::vk::Result::SUCCESS), Ok(()));
+        assert!(state.check(ash::vk::Result::ERROR_OUT_OF_DEVICE_MEMORY).is_err());
+        assert!(!state.lost);
+        assert_eq!(state.in_flight, [true, true]);
+
+        assert_eq!(state.check(ash::vk::Result::ERROR_DEVICE_LOST), Err(VocoderError::DeviceLost));
+        assert!(state.lost);
+        assert_eq!(state.losses, 3);
+        assert_eq!((state.in_flight, state.late), ([false; 2], [false; 2]));
+        // everything failing on the lost device is still the same loss
+        assert_eq!(state.check(ash::vk::Result::ERROR_DEVICE_LOST), Err(VocoderError::DeviceLost));
+        assert_eq!(state.losses, 3);
+    }
+
+    #[test]
+    fn recovery_backs_off() {
+        let start = Instant::now();
+        let mut state = DeviceState::new(0);
+        assert!(!state.recovery_due(start));
+        let _ = state.check(ash::vk::Result::ERROR_DEVICE_LOST);
+        assert!(state.recovery_due(start));
+
+        let mut at = start;
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            state.recovery_failed(at);
+            let next = state.next_recovery.unwrap();
+            assert!(!state.recovery_due(next - Duration::from_millis(1)));
+            assert!(state.recovery_due(next));
+            delays.push(next - at);
+            at = next;
+        }
+        let ms = |ms| Duration::from_millis(ms);
+        assert_eq!(delays, [ms(250), ms(500), ms(1000), ms(2000), ms(4000), ms(8000), ms(8000), ms(8000)]);
+        // the state a recovered vocoder starts from keeps only the count
+        let recovered = DeviceState::new(state.losses);
+        assert_eq!(recovered.losses, 1);
+        assert!(!recovered.recovery_due(at));
+    }
+
+    #[test]
+    fn retry_delay_saturates() {
+        assert_eq!(retry_delay(u32::MAX), LAST_RETRY);
+    }
+}
@@ -8,107 +8,348 @@ use vulkano::{
     descriptor_set::{
         allocator::DescriptorSetAllocator, PersistentDescriptorSet, WriteDescriptorSet, layout::DescriptorSetLayout,
     },
-    memory::allocator::{MemoryAllocator}, command_buffer::AutoCommandBufferBuilder,
+    memory::allocator::{MemoryAllocator},
+    command_buffer::{AutoCommandBufferBuilder, BufferCopy, CopyBufferInfoTyped},
 };
 
+use super::{setup_error, VocoderError, VocoderSettings};
 
-const MAX_WAVE_LENGTH: usize = 1024;
 
+/// Stream position at the start of a block. Kept on the host in f64 and reduced to
+/// the ranges the shaders need, so it neither overflows nor loses precision over time.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Clock {
+    pub analysis_length: usize,
+    /// Samples since the start, modulo `analysis_length`; every analysis bin is periodic in it.
+    pub t: usize,
+    /// Pitch shift read offset, `t * (ratio - 1)` integrated over ratio changes, modulo the delay.
+    pub warp: f64,
+    /// Frequency shift carrier phase in cycles, modulo 1.
+    pub shift_phase: f64,
+    /// Blocks processed so far.
+    pub block: u64,
+}
+
+impl Clock {
+    pub fn new(analysis_length: usize) -> Clock {
+        Clock { analysis_length, t: 0, warp: 0.0, shift_phase: 0.0, block: 0 }
+    }
+
+    pub fn advance(&mut self, length: usize, settings: &VocoderSettings) {
+        // same clamp as the shader applies to the delay
+        let delay = settings.delay.clamp(0.0, (self.analysis_length - 1) as f32) as f64 + 1.0;
+        let shift = settings.frequency_shift as f64 / settings.sample_rate as f64;
+        self.t = (self.t + length) % self.analysis_length;
+        self.warp = (self.warp + length as f64 * (settings.pitch_shift_ratio as f64 - 1.0)).rem_euclid(delay);
+        self.shift_phase = (self.shift_phase + length as f64 * shift).rem_euclid(1.0);
+        self.block += 1;
+    }
+
+    /// Whether the block about to be processed should end with an exact resync.
+    pub fn resync_due(&self, resync_interval: u32) -> bool {
+        // never for an interval of 0, as block + 1 is never 0
+        (self.block + 1).is_multiple_of(resync_interval as u64)
+    }
+}
+
+/// Everything is indexed by block parity. The vocoder pass of each block reads the history the
+/// other one wrote, and the buffers the host touches are doubled so one block can be in flight
+/// while the next is uploaded. Each buffer holds every stream of the batch back to back.
 pub struct SamplewiseFourierDescriptorSets<A: DescriptorSetAllocator + ?Sized> {
-    pub descriptor_set_ft: Arc<PersistentDescriptorSet<A::Alloc>>,
-    pub descriptor_set_ift: Arc<PersistentDescriptorSet<A::Alloc>>,
-    pub result_ft: Arc<DeviceLocalBuffer<[[[f32; 2]; MAX_WAVE_LENGTH]]>>,
-    pub result_ift: Arc<CpuAccessibleBuffer<[f32]>>,
-    input: Arc<CpuAccessibleBuffer<[f32]>>,
+    pub descriptor_sets_vocoder: [Arc<PersistentDescriptorSet<A::Alloc>>; 2],
+    pub descriptor_sets_resolve: [Arc<PersistentDescriptorSet<A::Alloc>>; 2],
+    results: [Arc<CpuAccessibleBuffer<[f32]>>; 2],
+    inputs: [Arc<CpuAccessibleBuffer<[f32]>>; 2],
+    times: [Arc<CpuAccessibleBuffer<[[f32; 4]]>>; 2],
+    input_buffer_length: usize,
 }
 
 impl<A: DescriptorSetAllocator + ?Sized> SamplewiseFourierDescriptorSets<A> {
+    /// `analysis_length` is the number of bins, and of samples the analysis spans.
+    /// `partial_count` is the number of workgroups the vocoder pass is dispatched as per stream.
+    #[allow(clippy::too_many_arguments)]
     pub fn new<L>(
         input_buffer_length: usize,
+        analysis_length: usize,
+        partial_count: usize,
+        streams: usize,
         memory_allocator: &(impl MemoryAllocator + ?Sized),
         descriptor_set_allocator: &A,
         command_buffer_builder: &mut AutoCommandBufferBuilder<L>,
-        set_layout_ft: Arc<DescriptorSetLayout>,
-        set_layout_ift: Arc<DescriptorSetLayout>,
-    ) -> SamplewiseFourierDescriptorSets<A>  {
-        let time_buffer = {
-            DeviceLocalBuffer::from_data(
-                memory_allocator,
-                0,
-                BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, command_buffer_builder,
-            ).unwrap()
+        set_layout_vocoder: Arc<DescriptorSetLayout>,
+        set_layout_resolve: Arc<DescriptorSetLayout>,
+    ) -> Result<SamplewiseFourierDescriptorSets<A>, VocoderError> {
+        // the transfer usages are for `record_upload` and `record_readback`
+        let time_buffer = || {
+            let data_iter = (0..streams).map(|_| [0.0f32; 4]);
+            CpuAccessibleBuffer::from_iter(
+                memory_allocator, BufferUsage {storage_buffer: true, transfer_dst: true, ..BufferUsage::empty()}, false,
+                data_iter,
+            ).map_err(setup_error)
         };
+        let time_buffers = [time_buffer()?, time_buffer()?];
         let state_buffer = {
-            let data_iter = (0..input_buffer_length).map(|_| [0, 0]);
+            let data_iter = (0..streams * analysis_length).map(|_| [0, 0]);
             DeviceLocalBuffer::from_iter(
                 memory_allocator,
                 data_iter,
                 BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, command_buffer_builder,
-            ).unwrap()
+            ).map_err(setup_error)?
         };
-        let input_buffer = {
-            let data_iter = (0..input_buffer_length).map(|_| 0.0f32);
+        let input_buffer = || {
+            let data_iter = (0..streams * input_buffer_length).map(|_| 0.0f32);
             CpuAccessibleBuffer::from_iter(
-                memory_allocator, BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, false,
+                memory_allocator, BufferUsage {storage_buffer: true, transfer_dst: true, ..BufferUsage::empty()}, false,
                 data_iter,
-            ).unwrap()
+            ).map_err(setup_error)
         };
-        let history_buffer = {
-            let data_iter = (0..MAX_WAVE_LENGTH).map(|_| 0.0f32);
+        let input_buffers = [input_buffer()?, input_buffer()?];
+        let mut history_buffer = || {
+            let data_iter = (0..streams * analysis_length).map(|_| 0.0f32);
             DeviceLocalBuffer::from_iter(
                 memory_allocator,
-                data_iter, 
+                data_iter,
                 BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, command_buffer_builder,
-            ).unwrap()
+            ).map_err(setup_error)
         };
-        let ft_result_buffer = {
-            let data_iter = (0..input_buffer_length).map(|_| [[0.0f32, 0.0f32]; MAX_WAVE_LENGTH]);
+        let history_buffers = [history_buffer()?, history_buffer()?];
+        let partial_buffer = {
+            let data_iter = (0..streams * partial_count * input_buffer_length).map(|_| 0.0f32);
             DeviceLocalBuffer::from_iter(
                 memory_allocator,
                 data_iter,
                 BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, command_buffer_builder,
-            ).unwrap()
+            ).map_err(setup_error)?
         };
-        let ift_result_buffer = {
-            let data_iter = (0..input_buffer_length).map(|_| 0.0f32);
+        let result_buffer = || {
+            let data_iter = (0..streams * input_buffer_length).map(|_| 0.0f32);
             CpuAccessibleBuffer::from_iter(
-                memory_allocator, BufferUsage {storage_buffer: true, ..BufferUsage::empty()}, false,
+                memory_allocator, BufferUsage {storage_buffer: true, transfer_src: true, ..BufferUsage::empty()}, false,
                 data_iter,
-            ).unwrap()
+            ).map_err(setup_error)
         };
+        let result_buffers = [result_buffer()?, result_buffer()?];
     
-        let set_ft = PersistentDescriptorSet::new(
+        let set_vocoder = |parity: usize| PersistentDescriptorSet::new(
             descriptor_set_allocator,
-            set_layout_ft.clone(),
+            set_layout_vocoder.clone(),
             [
-                WriteDescriptorSet::buffer(0, time_buffer.clone()),
+                WriteDescriptorSet::buffer(0, time_buffers[parity].clone()),
                 WriteDescriptorSet::buffer(1, state_buffer.clone()),
-                WriteDescriptorSet::buffer(2, input_buffer.clone()),
-                WriteDescriptorSet::buffer(3, history_buffer.clone()),
-                WriteDescriptorSet::buffer(4, ft_result_buffer.clone()),
+                WriteDescriptorSet::buffer(2, input_buffers[parity].clone()),
+                WriteDescriptorSet::buffer(3, history_buffers[parity].clone()),
+                WriteDescriptorSet::buffer(4, partial_buffer.clone()),
+                WriteDescriptorSet::buffer(5, history_buffers[1 - parity].clone()),
             ],
-        ).unwrap();
+        ).map_err(setup_error);
+        let sets_vocoder = [set_vocoder(0)?, set_vocoder(1)?];
     
-        let set_ift = PersistentDescriptorSet::new(
+        let set_resolve = |parity: usize| PersistentDescriptorSet::new(
             descriptor_set_allocator,
-            set_layout_ift.clone(),
+            set_layout_resolve.clone(),
             [
-                WriteDescriptorSet::buffer(0, time_buffer.clone()),
-                WriteDescriptorSet::buffer(1, ft_result_buffer.clone()),
-                WriteDescriptorSet::buffer(2, ift_result_buffer.clone()),
+                WriteDescriptorSet::buffer(0, partial_buffer.clone()),
+                WriteDescriptorSet::buffer(1, result_buffers[parity].clone()),
             ],
-        ).unwrap();
-
-        SamplewiseFourierDescriptorSets {
-            descriptor_set_ft: set_ft,
-            descriptor_set_ift: set_ift,
-            result_ft: ft_result_buffer,
-            result_ift: ift_result_buffer,
-            input: input_buffer,
+        ).map_err(setup_error);
+        let sets_resolve = [set_resolve(0)?, set_resolve(1)?];
+
+        Ok(SamplewiseFourierDescriptorSets {
+            descriptor_sets_vocoder: sets_vocoder,
+            descriptor_sets_resolve: sets_resolve,
+            results: result_buffers,
+            inputs: input_buffers,
+            times: time_buffers,
+            input_buffer_length,
+        })
+    }
+    pub fn update_input(&mut self, parity: usize, stream: usize, src: &[f32]) {
+        let mut input_buffer_content = self.inputs[parity].write().unwrap();
+        input_buffer_content[self.stream_range(stream)].clone_from_slice(src);
+    }
+    /// `resync` recomputes the stream's running state exactly at the end of the coming block.
+    pub fn update_time(&mut self, parity: usize, stream: usize, clock: &Clock, resync: bool) {
+        self.times[parity].write().unwrap()[stream] = time_entry(clock, resync);
+    }
+    pub fn read_result(&self, parity: usize, stream: usize, dest: &mut [f32]) {
+        dest.copy_from_slice(&self.results[parity].read().unwrap()[self.stream_range(stream)]);
+    }
+
+    /// Records copying block `block` of `staging` into the first stream's input and time,
+    /// in place of `update_input` and `update_time`.
+    pub fn record_upload<L>(
+        &self, command_buffer_builder: &mut AutoCommandBufferBuilder<L>, staging: &Staging, block: usize, parity: usize,
+    ) -> Result<(), VocoderError> {
+        command_buffer_builder
+            .copy_buffer(CopyBufferInfoTyped {
+                regions: [BufferCopy {
+                    src_offset: (block * self.input_buffer_length) as u64,
+                    size: self.input_buffer_length as u64,
+                    ..Default::default()
+                }].into(),
+                ..CopyBufferInfoTyped::buffers(staging.inputs.clone(), self.inputs[parity].clone())
+            }).map_err(setup_error)?
+            .copy_buffer(CopyBufferInfoTyped {
+                regions: [BufferCopy { src_offset: block as u64, size: 1, ..Default::default() }].into(),
+                ..CopyBufferInfoTyped::buffers(staging.times.clone(), self.times[parity].clone())
+            }).map_err(setup_error)?;
+        Ok(())
+    }
+    /// Records copying the first stream's result into block `block` of `staging`, in place of `read_result`.
+    pub fn record_readback<L>(
+        &self, command_buffer_builder: &mut AutoCommandBufferBuilder<L>, staging: &Staging, block: usize, parity: usize,
+    ) -> Result<(), VocoderError> {
+        command_buffer_builder
+            .copy_buffer(CopyBufferInfoTyped {
+                regions: [BufferCopy {
+                    dst_offset: (block * self.input_buffer_length) as u64,
+                    size: self.input_buffer_length as u64,
+                    ..Default::default()
+                }].into(),
+                ..CopyBufferInfoTyped::buffers(self.results[parity].clone(), staging.results.clone())
+            }).map_err(setup_error)?;
+        Ok(())
+    }
+
+    fn stream_range(&self, stream: usize) -> std::ops::Range<usize> {
+        stream * self.input_buffer_length..(stream + 1) * self.input_buffer_length
+    }
+}
+
+/// Inputs, times and results of many consecutive blocks of one stream, for a single command buffer
+/// to move through the per-parity buffers block after block.
+pub struct Staging {
+    inputs: Arc<CpuAccessibleBuffer<[f32]>>,
+    times: Arc<CpuAccessibleBuffer<[[f32; 4]]>>,
+    results: Arc<CpuAccessibleBuffer<[f32]>>,
+}
+
+impl Staging {
+    /// `times` holds one `time_entry` per block of `src`.
+    pub fn new(
+        memory_allocator: &(impl MemoryAllocator + ?Sized), src: &[f32], times: Vec<[f32; 4]>,
+    ) -> Result<Staging, VocoderError> {
+        let inputs = CpuAccessibleBuffer::from_iter(
+            memory_allocator, BufferUsage {transfer_src: true, ..BufferUsage::empty()}, false,
+            src.iter().copied(),
+        ).map_err(setup_error)?;
+        let times = CpuAccessibleBuffer::from_iter(
+            memory_allocator, BufferUsage {transfer_src: true, ..BufferUsage::empty()}, false,
+            times,
+        ).map_err(setup_error)?;
+        let results = CpuAccessibleBuffer::from_iter(
+            memory_allocator, BufferUsage {transfer_dst: true, ..BufferUsage::empty()}, false,
+            src.iter().map(|_| 0.0f32),
+        ).map_err(setup_error)?;
+        Ok(Staging { inputs, times, results })
+    }
+
+    pub fn read_results(&self, dest: &mut [f32]) {
+        dest.copy_from_slice(&self.results.read().unwrap());
+    }
+}
+
+/// What the shader reads from the time buffer for a block starting at `clock`.
+pub fn time_entry(clock: &Clock, resync: bool) -> [f32; 4] {
+    let resync = if resync { 1.0 } else { 0.0 };
+    [clock.t as f32, clock.warp as f32, clock.shift_phase as f32, resync]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_stays_bounded_and_continuous() {
+        let block = 1024;
+        let settings = VocoderSettings {
+            pitch_shift_ratio: 1.5,
+            frequency_shift: 100.0,
+            ..VocoderSettings::default()
+        };
+        let delay = settings.delay as f64 + 1.0;
+        let mut clock = Clock::new(1024);
+        // about six hours at 48kHz
+        for n in 1..=1_000_000u64 {
+            clock.advance(block, &settings);
+            assert!(clock.t < clock.analysis_length);
+            assert!((0.0..delay).contains(&clock.warp));
+            assert!((0.0..1.0).contains(&clock.shift_phase));
+            if n % 100_000 == 0 {
+                let samples = (n * block as u64) as f64;
+                let warp = (samples * 0.5).rem_euclid(delay);
+                assert!((clock.warp - warp).abs() < 1e-6);
+            }
+        }
+
+        let mut clock = Clock::new(1024);
+        let unity = VocoderSettings::default();
+        clock.advance(block, &unity);
+        assert_eq!(clock.warp, 0.0);
+        assert_eq!(clock.shift_phase, 0.0);
+    }
+
+    #[test]
+    fn fractional_clock_does_not_drift() {
+        // a block length that does not divide the analysis length, ratios either side of 1 and a
+        // shift whose phase increment is no binary fraction
+        let length = 1000;
+        for ratio in [1.37, 0.73] {
+            let settings = VocoderSettings {
+                pitch_shift_ratio: ratio,
+                frequency_shift: 37.3,
+                sample_rate: 44100.0,
+                ..VocoderSettings::default()
+            };
+            let delay = settings.delay as f64 + 1.0;
+            let shift = settings.frequency_shift as f64 / settings.sample_rate as f64;
+            // distance on a circle of circumference `period`, as both sides may wrap apart
+            let wrapped_distance = |a: f64, b: f64, period: f64| {
+                let d = (a - b).rem_euclid(period);
+                d.min(period - d)
+            };
+            let mut clock = Clock::new(1024);
+            for n in 1..=1_000_000u64 {
+                clock.advance(length, &settings);
+                let samples = n * length as u64;
+                assert_eq!(clock.t as u64, samples % 1024);
+                assert!((0.0..delay).contains(&clock.warp));
+                assert!((0.0..1.0).contains(&clock.shift_phase));
+                if n % 50_000 == 0 {
+                    let warp = samples as f64 * (settings.pitch_shift_ratio as f64 - 1.0);
+                    assert!(wrapped_distance(clock.warp, warp, delay) < 1e-6, "warp off after {} blocks", n);
+                    let phase = samples as f64 * shift;
+                    assert!(wrapped_distance(clock.shift_phase, phase, 1.0) < 1e-9, "phase off after {} blocks", n);
+                }
+            }
+            assert_eq!(clock.block, 1_000_000);
         }
     }
-    pub fn update_input(&mut self, src: &[f32]) {
-        let mut input_buffer_content = self.input.write().unwrap();
-        input_buffer_content.clone_from_slice(src);
+
+    #[test]
+    fn resync_follows_interval() {
+        let settings = VocoderSettings::default();
+        let mut clock = Clock::new(1024);
+        let mut due = Vec::new();
+        for _ in 0..8 {
+            due.push(clock.resync_due(4));
+            assert!(!clock.resync_due(0));
+            clock.advance(1024, &settings);
+        }
+        assert_eq!(due, [false, false, false, true, false, false, false, true]);
+    }
+
+    #[test]
+    fn clock_wraps_at_analysis_length() {
+        let settings = VocoderSettings::default();
+        let mut clock = Clock::new(256);
+        clock.advance(1024, &settings);
+        assert_eq!(clock.t, 0);
+        let mut clock = Clock::new(4096);
+        for n in 1..=4 {
+            clock.advance(1024, &settings);
+            assert_eq!(clock.t, (n * 1024) % 4096);
+        }
     }
 }
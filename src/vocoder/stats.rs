@@ -0,0 +1,144 @@
+use std::time::Duration;
+
+use super::BLOCK_LENGTH;
+
+
+// blocks the statistics look back over
+const WINDOW: usize = 1024;
+
+/// Mean, 99th percentile and worst duration of one stage over the recent blocks.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StageStats {
+    pub mean: Duration,
+    pub p99: Duration,
+    pub worst: Duration,
+}
+
+/// Timing of the last blocks, see `Vocoder::stats`. A stage is `None` until it was timed once;
+/// the dispatches stay `None` on queues without timestamp support.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct VocoderStats {
+    /// Copying the input and clock state into the GPU's buffers
+    pub upload: Option<StageStats>,
+    /// The vocoder dispatch, analysing and resynthesising every bin in one pass, timed on the GPU
+    pub vocoder_dispatch: Option<StageStats>,
+    /// The resolve dispatch, summing the bins' partial outputs into samples, timed on the GPU
+    pub resolve_dispatch: Option<StageStats>,
+    /// Copying the output out of the GPU's buffers
+    pub readback: Option<StageStats>,
+    /// Wall-clock time from handing a block to the queue to seeing its fence signalled
+    pub submit_to_fence: Option<StageStats>,
+}
+
+impl VocoderStats {
+    /// How many times faster than real time a block goes through on average at `sample_rate`,
+    /// counting upload, submit to fence and readback. Below 1 the vocoder cannot keep up.
+    pub fn realtime_factor(&self, sample_rate: f32) -> Option<f64> {
+        let block_time = self.upload?.mean + self.submit_to_fence?.mean + self.readback?.mean;
+        Some(BLOCK_LENGTH as f64 / sample_rate as f64 / block_time.as_secs_f64())
+    }
+}
+
+
+// Durations of the last `WINDOW` blocks, in a buffer allocated up front as they are recorded
+// on the audio thread.
+pub(super) struct Rolling {
+    nanos: Vec<u64>,
+    next: usize,
+    len: usize,
+}
+
+impl Rolling {
+    fn new() -> Self {
+        Rolling { nanos: vec![0; WINDOW], next: 0, len: 0 }
+    }
+
+    pub fn record(&mut self, duration: Duration) {
+        self.nanos[self.next] = duration.as_nanos().min(u64::MAX as u128) as u64;
+        self.next = (self.next + 1) % WINDOW;
+        self.len = (self.len + 1).min(WINDOW);
+    }
+
+    fn summary(&self) -> Option<StageStats> {
+        if self.len == 0 {
+            return None;
+        }
+        let mut sorted = self.nanos[..self.len].to_vec();
+        sorted.sort_unstable();
+        let mean = sorted.iter().map(|&n| n as u128).sum::<u128>() / self.len as u128;
+        let p99 = sorted[(self.len * 99).div_ceil(100) - 1];
+        Some(StageStats {
+            mean: Duration::from_nanos(mean as u64),
+            p99: Duration::from_nanos(p99),
+            worst: Duration::from_nanos(sorted[self.len - 1]),
+        })
+    }
+}
+
+pub(super) struct Timings {
+    pub upload: Rolling,
+    pub vocoder_dispatch: Rolling,
+    pub resolve_dispatch: Rolling,
+    pub readback: Rolling,
+    pub submit_to_fence: Rolling,
+}
+
+impl Timings {
+    pub fn new() -> Self {
+        Timings {
+            upload: Rolling::new(),
+            vocoder_dispatch: Rolling::new(),
+            resolve_dispatch: Rolling::new(),
+            readback: Rolling::new(),
+            submit_to_fence: Rolling::new(),
+        }
+    }
+
+    pub fn stats(&self) -> VocoderStats {
+        VocoderStats {
+            upload: self.upload.summary(),
+            vocoder_dispatch: self.vocoder_dispatch.summary(),
+            resolve_dispatch: self.resolve_dispatch.summary(),
+            readback: self.readback.summary(),
+            submit_to_fence: self.submit_to_fence.summary(),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn micros(micros: u64) -> Duration {
+        Duration::from_micros(micros)
+    }
+
+    #[test]
+    fn summary_covers_the_last_window() {
+        let mut rolling = Rolling::new();
+        assert_eq!(rolling.summary(), None);
+        // an early outlier, then pushed out of the window
+        rolling.record(micros(5000));
+        for block in 0..WINDOW as u64 {
+            rolling.record(micros(100 + block % 100));
+        }
+        let summary = rolling.summary().unwrap();
+        assert_eq!(summary.worst, micros(199));
+        assert_eq!(summary.p99, micros(198));
+        let total: u64 = (0..WINDOW as u64).map(|block| 100 + block % 100).sum();
+        assert_eq!(summary.mean, Duration::from_nanos(total * 1000 / WINDOW as u64));
+    }
+
+    #[test]
+    fn realtime_factor_compares_block_time_to_audio_time() {
+        let mut timings = Timings::new();
+        assert_eq!(timings.stats().realtime_factor(48000.0), None);
+        timings.upload.record(micros(100));
+        timings.submit_to_fence.record(micros(1800));
+        timings.readback.record(micros(100));
+        // 1024 samples at 48 kHz last 21.33 ms, over ten times the 2 ms a block takes
+        let factor = timings.stats().realtime_factor(48000.0).unwrap();
+        assert!((factor - 1024.0 / 48.0 / 2.0).abs() < 1e-9);
+    }
+}
@@ -1,34 +1,73 @@
 
 use std::{
+    fmt,
     sync::Arc,
 };
 
 use vulkano::{
     device::{
-        physical::PhysicalDeviceType, Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo,
+        physical::{PhysicalDevice, PhysicalDeviceType}, Device, DeviceCreateInfo, DeviceCreationError, DeviceExtensions, Queue, QueueCreateInfo,
     },
-    instance::{Instance, InstanceCreateInfo},
-    VulkanLibrary,
+    instance::{Instance, InstanceCreateInfo, InstanceCreationError},
+    LoadingError, VulkanLibrary,
 };
 
-pub fn create_vulcan_device() -> (Arc<Device>, impl ExactSizeIterator<Item = Arc<Queue>>) {
-    let library = VulkanLibrary::new().unwrap();
-    let instance = Instance::new(
+
+/// Why `try_create_vulcan_device` has no device to offer.
+#[derive(Debug)]
+pub enum DeviceSetupError {
+    /// No Vulkan loader could be found.
+    Library(LoadingError),
+    Instance(InstanceCreationError),
+    /// No physical device can run the vocoder.
+    NoComputeDevice,
+    Device(DeviceCreationError),
+}
+
+impl fmt::Display for DeviceSetupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeviceSetupError::Library(e) => write!(f, "could not load Vulkan: {}", e),
+            DeviceSetupError::Instance(e) => write!(f, "could not create a Vulkan instance: {}", e),
+            DeviceSetupError::NoComputeDevice => write!(f, "no Vulkan device with compute support"),
+            DeviceSetupError::Device(e) => write!(f, "could not open the Vulkan device: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DeviceSetupError {}
+
+fn device_extensions() -> DeviceExtensions {
+    DeviceExtensions {
+        khr_storage_buffer_storage_class: true,
+        ..DeviceExtensions::empty()
+    }
+}
+
+pub fn create_vulcan_instance() -> Arc<Instance> {
+    try_create_vulcan_instance().unwrap()
+}
+
+pub fn try_create_vulcan_instance() -> Result<Arc<Instance>, DeviceSetupError> {
+    let library = VulkanLibrary::new().map_err(DeviceSetupError::Library)?;
+    Instance::new(
         library,
         InstanceCreateInfo {
             enumerate_portability: true,
             ..Default::default()
         },
     )
-    .unwrap();
+    .map_err(DeviceSetupError::Instance)
+}
 
-    let device_extensions = DeviceExtensions {
-        khr_storage_buffer_storage_class: true,
-        ..DeviceExtensions::empty()
-    };
-    let (physical_device, queue_family_index) = instance
+/// Physical devices able to run the vocoder with their compute queue family, best first.
+/// Empty if they cannot be enumerated, e.g. after a driver crash.
+pub fn enumerate_compute_devices(instance: &Arc<Instance>) -> Vec<(Arc<PhysicalDevice>, u32)> {
+    let device_extensions = device_extensions();
+    let mut devices: Vec<(Arc<PhysicalDevice>, u32)> = instance
         .enumerate_physical_devices()
-        .unwrap()
+        .into_iter()
+        .flatten()
         .filter(|p| p.supported_extensions().contains(&device_extensions))
         .filter_map(|p| {
             p.queue_family_properties()
@@ -36,25 +75,52 @@ pub fn create_vulcan_device() -> (Arc<Device>, impl ExactSizeIterator<Item = Arc
                 .position(|q| q.queue_flags.compute)
                 .map(|i| (p, i as u32))
         })
-        .min_by_key(|(p, _)| match p.properties().device_type {
-            PhysicalDeviceType::DiscreteGpu => 0,
-            PhysicalDeviceType::IntegratedGpu => 1,
-            PhysicalDeviceType::VirtualGpu => 2,
-            PhysicalDeviceType::Cpu => 3,
-            PhysicalDeviceType::Other => 4,
-            _ => 5,
-        })
-        .unwrap();
+        .collect();
+    devices.sort_by_key(|(p, _)| match p.properties().device_type {
+        PhysicalDeviceType::DiscreteGpu => 0,
+        PhysicalDeviceType::IntegratedGpu => 1,
+        PhysicalDeviceType::VirtualGpu => 2,
+        PhysicalDeviceType::Cpu => 3,
+        PhysicalDeviceType::Other => 4,
+        _ => 5,
+    });
+    devices
+}
+
+pub fn create_vulcan_device_on(
+    physical_device: Arc<PhysicalDevice>, queue_family_index: u32,
+) -> (Arc<Device>, impl ExactSizeIterator<Item = Arc<Queue>>) {
+    try_create_vulcan_device_on(physical_device, queue_family_index).unwrap()
+}
 
+/// Like `create_vulcan_device_on`, for callers that can go on without a device.
+pub fn try_create_vulcan_device_on(
+    physical_device: Arc<PhysicalDevice>, queue_family_index: u32,
+) -> Result<(Arc<Device>, impl ExactSizeIterator<Item = Arc<Queue>>), DeviceCreationError> {
     Device::new(
         physical_device,
         DeviceCreateInfo {
-            enabled_extensions: device_extensions,
+            enabled_extensions: device_extensions(),
             queue_create_infos: vec![QueueCreateInfo {
                 queue_family_index,
                 ..Default::default()
             }],
             ..Default::default()
         },
-    ).unwrap()
+    )
+}
+
+pub fn create_vulcan_device() -> (Arc<Device>, impl ExactSizeIterator<Item = Arc<Queue>>) {
+    try_create_vulcan_device().unwrap()
+}
+
+/// Like `create_vulcan_device`, for hosts that must not panic when there is no usable GPU.
+pub fn try_create_vulcan_device(
+) -> Result<(Arc<Device>, impl ExactSizeIterator<Item = Arc<Queue>>), DeviceSetupError> {
+    let instance = try_create_vulcan_instance()?;
+    let (physical_device, queue_family_index) = enumerate_compute_devices(&instance)
+        .into_iter()
+        .next()
+        .ok_or(DeviceSetupError::NoComputeDevice)?;
+    try_create_vulcan_device_on(physical_device, queue_family_index).map_err(DeviceSetupError::Device)
 }
@@ -0,0 +1,71 @@
+//! A `VocoderBatch` of K streams must sound exactly like K independent `Vocoder`s, each stream
+//! with its own settings, including changes to one stream while the others carry on.
+
+mod common;
+
+use common::{bitwise_equal, context, process_blockwise};
+use vocoder_volcano::vocoder::{
+    AnalysisWindow, FrequencyShiftMode, Vocoder, VocoderBatch, VocoderSettings, BLOCK_LENGTH, DEFAULT_ANALYSIS_LENGTH,
+};
+
+
+const BLOCKS: usize = 20;
+// the block from which on the second stream's settings change
+const CHANGE: usize = 7;
+
+fn stream_settings() -> Vec<VocoderSettings> {
+    let settings = VocoderSettings { resync_interval: 5, ..VocoderSettings::default() };
+    vec![
+        VocoderSettings { pitch_shift_ratio: 1.3, ..settings.clone() },
+        VocoderSettings { pitch_shift_ratio: 0.8, frequency_shift: 60.0, ..settings.clone() },
+        VocoderSettings {
+            equalizer: [0.0, 2.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            frequency_shift: 25.0,
+            frequency_shift_mode: FrequencyShiftMode::RingModulation,
+            ..settings.clone()
+        },
+        VocoderSettings { pitch_shift_ratio: 1.1, analysis_window: AnalysisWindow::Hann, ..settings },
+    ]
+}
+
+fn changed(settings: &VocoderSettings) -> VocoderSettings {
+    VocoderSettings { pitch_shift_ratio: 1.5, analysis_window: AnalysisWindow::Blackman, ..settings.clone() }
+}
+
+fn input(stream: usize) -> Vec<f32> {
+    (0..BLOCKS * BLOCK_LENGTH).map(|i| (i as f32 * (0.02 + 0.007 * stream as f32)).sin() * 0.5).collect()
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn batch_matches_independent_vocoders() {
+    let context = context();
+    let settings = stream_settings();
+    let inputs: Vec<Vec<f32>> = (0..settings.len()).map(input).collect();
+
+    let expected: Vec<Vec<f32>> = settings.iter().zip(&inputs).enumerate().map(|(stream, (settings, src))| {
+        let mut vocoder = Vocoder::with_context(&context, settings.clone(), DEFAULT_ANALYSIS_LENGTH);
+        process_blockwise(&mut vocoder, src, |block, vocoder| {
+            if stream == 1 && block == CHANGE {
+                vocoder.update_settings(&changed(settings));
+            }
+        })
+    }).collect();
+
+    let mut batch = VocoderBatch::new(&context, &settings, DEFAULT_ANALYSIS_LENGTH).unwrap();
+    let mut output = vec![vec![0.0f32; BLOCKS * BLOCK_LENGTH]; settings.len()];
+    for block in 0..BLOCKS {
+        if block == CHANGE {
+            batch.update_settings(1, &changed(&settings[1]));
+        }
+        let range = block * BLOCK_LENGTH..(block + 1) * BLOCK_LENGTH;
+        let src: Vec<&[f32]> = inputs.iter().map(|input| &input[range.clone()]).collect();
+        let mut dest: Vec<&mut [f32]> = output.iter_mut().map(|output| &mut output[range.clone()]).collect();
+        batch.process(&src, &mut dest).unwrap();
+    }
+
+    for (stream, (output, expected)) in output.iter().zip(&expected).enumerate() {
+        assert!(expected.iter().any(|&s| s != 0.0));
+        assert!(bitwise_equal(output, expected), "stream {} differs from its own vocoder", stream);
+    }
+}
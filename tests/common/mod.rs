@@ -0,0 +1,45 @@
+//! What the GPU tests share: a device, the test signal, blockwise processing and a bit-exact
+//! comparison. The unit tests in `src/vocoder.rs` include this file too.
+#![allow(dead_code)]
+
+use std::sync::Arc;
+
+use vulkano::device::Queue;
+
+use vocoder_volcano::vulcan_helper::create_vulcan_device;
+use vocoder_volcano::vocoder::{AudioFilter, VocoderContext, BLOCK_LENGTH};
+
+
+/// A context on the preferred device, with all its queues.
+pub fn context() -> Arc<VocoderContext> {
+    let (device, queues) = create_vulcan_device();
+    VocoderContext::new(device, queues)
+}
+
+/// A queue of the preferred device.
+pub fn queue() -> Arc<Queue> {
+    let (_, mut queues) = create_vulcan_device();
+    queues.next().unwrap()
+}
+
+/// `length` samples of the signal most tests feed in.
+pub fn sine(length: usize) -> Vec<f32> {
+    (0..length).map(|i| (i as f32 * 0.031).sin() * 0.5).collect()
+}
+
+/// `src` through `filter` block by block, calling `before_block` with each block's index first,
+/// e.g. to change settings on the way.
+pub fn process_blockwise<F: AudioFilter>(
+    filter: &mut F, src: &[f32], mut before_block: impl FnMut(usize, &mut F),
+) -> Vec<f32> {
+    let mut dest = vec![0.0f32; src.len()];
+    for (block, (src, dest)) in src.chunks(BLOCK_LENGTH).zip(dest.chunks_mut(BLOCK_LENGTH)).enumerate() {
+        before_block(block, filter);
+        filter.process(src, dest);
+    }
+    dest
+}
+
+pub fn bitwise_equal(a: &[f32], b: &[f32]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.to_bits() == b.to_bits())
+}
@@ -0,0 +1,62 @@
+//! `Vocoder::process` runs on audio threads, so it must not touch the heap.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+use vocoder_volcano::vulcan_helper::create_vulcan_device;
+use vocoder_volcano::vocoder::{AudioFilter, AnalysisWindow, Vocoder, VocoderSettings, BLOCK_LENGTH};
+
+
+struct CountingAllocator;
+
+static COUNTING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if COUNTING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn process_does_not_allocate() {
+    let (_, mut queues) = create_vulcan_device();
+    let settings = VocoderSettings {
+        resync_interval: 2,
+        ..VocoderSettings::default()
+    };
+    let mut vocoder = Vocoder::new(queues.next().unwrap(), settings.clone());
+    // timing is recorded on the audio thread too
+    vocoder.set_stats_enabled(true);
+    let src: Vec<f32> = (0..BLOCK_LENGTH).map(|i| (i as f32 * 0.05).sin()).collect();
+    let mut dest = vec![0.0f32; BLOCK_LENGTH];
+    // both block parities, a resync, and a window change
+    vocoder.process(&src, &mut dest);
+    vocoder.update_settings(&VocoderSettings { analysis_window: AnalysisWindow::Hann, ..settings });
+
+    COUNTING.store(true, Ordering::Relaxed);
+    for _ in 0..8 {
+        vocoder.process(&src, &mut dest);
+    }
+    COUNTING.store(false, Ordering::Relaxed);
+    assert_eq!(ALLOCATIONS.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,72 @@
+"""Tests for the Python module. Build it into the environment first with `maturin develop`,
+then run `pytest`. The tests needing a Vulkan device are skipped without one."""
+
+import numpy as np
+import pytest
+
+import vocoder_volcano as vv
+
+
+needs_device = pytest.mark.skipif(not vv.list_devices(), reason="needs a Vulkan device")
+
+
+def signal(frames, channels=None):
+    t = np.arange(frames, dtype=np.float32)
+    mono = 0.5 * np.sin(t * 0.031).astype(np.float32)
+    return mono if channels is None else np.stack([mono * (c + 1) / channels for c in range(channels)], axis=1)
+
+
+def test_settings_keywords():
+    settings = vv.VocoderSettings(pitch_shift_ratio=1.5, resync_interval=3)
+    assert settings.pitch_shift_ratio == 1.5
+    assert settings.resync_interval == 3
+    assert settings.delay == vv.VocoderSettings().delay
+    with pytest.raises(TypeError):
+        vv.VocoderSettings(no_such_setting=1.0)
+
+
+def test_presets():
+    assert "radio" in vv.BUILTIN_PRESETS
+    assert list(vv.VocoderSettings.preset("radio").equalizer[:3]) == [0.0, 6.0, -6.0]
+    with pytest.raises(ValueError):
+        vv.VocoderSettings.preset("no such preset")
+
+
+def test_analysis_length_is_checked():
+    for analysis_length in (100, 128, 8192):
+        with pytest.raises(ValueError):
+            vv.Vocoder(analysis_length=analysis_length)
+
+
+@needs_device
+def test_process_keeps_the_shape_and_lags():
+    vocoder = vv.Vocoder()
+    for samples in (signal(3000), signal(3000, channels=2)):
+        vocoder.reset()
+        output = vocoder.process(samples)
+        assert output.shape == samples.shape
+        assert output.dtype == np.float32
+        assert not output[: vocoder.latency].any()
+        assert np.isfinite(output).all()
+
+
+@needs_device
+def test_render_lines_up_and_starts_afresh():
+    vocoder = vv.Vocoder(vv.VocoderSettings(pitch_shift_ratio=1.3))
+    samples = signal(5000, channels=2)
+    rendered = vocoder.render(samples)
+    assert rendered.shape == samples.shape
+    # rendering again, after streaming through the same vocoder, must not carry any state over
+    vocoder.process(signal(777, channels=2))
+    assert np.array_equal(vocoder.render(samples), rendered)
+    # rendered output is `process` output moved back by the latency
+    streamed = vv.Vocoder(vv.VocoderSettings(pitch_shift_ratio=1.3)).process(
+        np.concatenate([samples, np.zeros((vocoder.latency, 2), np.float32)])
+    )
+    assert np.array_equal(streamed[vocoder.latency :], rendered)
+
+
+@needs_device
+def test_missing_device_raises():
+    with pytest.raises(RuntimeError):
+        vv.Vocoder(device=len(vv.list_devices()))
@@ -0,0 +1,52 @@
+//! `Vocoder::render` batches blocks into few submissions, but must sound exactly like `process`.
+
+mod common;
+
+use common::{bitwise_equal, process_blockwise, queue, sine};
+use vocoder_volcano::vocoder::{AudioFilter, Vocoder, VocoderError, VocoderSettings, BLOCK_LENGTH};
+
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn render_matches_process() {
+    let queue = queue();
+    let settings = VocoderSettings {
+        pitch_shift_ratio: 1.3,
+        frequency_shift: 40.0,
+        resync_interval: 7,
+        ..VocoderSettings::default()
+    };
+    // several submissions' worth, ending in a partial block
+    let length = 600 * BLOCK_LENGTH + 100;
+    let rendered_length = length.div_ceil(BLOCK_LENGTH) * BLOCK_LENGTH;
+    let src = sine(length);
+    let tail = sine(4 * BLOCK_LENGTH);
+
+    // what `render` amounts to: the last block padded with silence; the tail then follows
+    let mut blockwise_src = src.clone();
+    blockwise_src.resize(rendered_length, 0.0);
+    blockwise_src.extend_from_slice(&tail);
+    let expected = process_blockwise(&mut Vocoder::new(queue.clone(), settings.clone()), &blockwise_src, |_, _| {});
+
+    let mut rendered = Vocoder::new(queue, settings);
+    let output = rendered.render(&src).unwrap();
+    assert_eq!(output.len(), length);
+    assert!(bitwise_equal(&output, &expected[..length]));
+    let mut dest = vec![0.0f32; BLOCK_LENGTH];
+    for (src, expected) in tail.chunks(BLOCK_LENGTH).zip(expected[rendered_length..].chunks(BLOCK_LENGTH)) {
+        rendered.process(src, &mut dest);
+        assert!(bitwise_equal(&dest, expected));
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn render_waits_for_submitted_blocks() {
+    let mut vocoder = Vocoder::new(queue(), VocoderSettings::default());
+    let src = sine(BLOCK_LENGTH);
+    let mut dest = vec![0.0f32; BLOCK_LENGTH];
+    let ticket = vocoder.submit(&src).unwrap();
+    assert_eq!(vocoder.render(&src), Err(VocoderError::Busy));
+    vocoder.collect(ticket, &mut dest).unwrap();
+    assert_eq!(vocoder.render(&src).unwrap().len(), BLOCK_LENGTH);
+}
@@ -0,0 +1,90 @@
+//! `submit`, `poll` and `collect` with two blocks in flight must sound exactly like `process`,
+//! settings changes included, and `PipelinedVocoder` exactly like it one block later.
+
+mod common;
+
+use common::{bitwise_equal, process_blockwise, queue, sine};
+use vocoder_volcano::vocoder::{PipelinedVocoder, Vocoder, VocoderError, VocoderSettings, BLOCK_LENGTH};
+
+
+const BLOCKS: usize = 24;
+// the block from which on the settings change
+const CHANGE: usize = 9;
+
+fn settings(block: usize) -> VocoderSettings {
+    let settings = VocoderSettings { resync_interval: 5, ..VocoderSettings::default() };
+    match block < CHANGE {
+        true => VocoderSettings { pitch_shift_ratio: 1.3, ..settings },
+        false => VocoderSettings {
+            pitch_shift_ratio: 0.8,
+            equalizer: [0.0, 2.0, -1.0, 0.0, 0.0, 0.0, 0.0, 0.0],
+            frequency_shift: 60.0,
+            ..settings
+        },
+    }
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn submitted_blocks_match_process() {
+    let queue = queue();
+    let src = sine(BLOCKS * BLOCK_LENGTH);
+    let mut blockwise = Vocoder::new(queue.clone(), settings(0));
+    let expected = process_blockwise(&mut blockwise, &src, |block, vocoder| vocoder.update_settings(&settings(block)));
+
+    // block N+1 is submitted, with its own settings, while block N is still in flight
+    let mut output = vec![0.0f32; src.len()];
+    let mut vocoder = Vocoder::new(queue, settings(0));
+    let mut pending = None;
+    for (block, src) in src.chunks(BLOCK_LENGTH).enumerate() {
+        vocoder.update_settings(&settings(block));
+        let ticket = vocoder.submit(src).unwrap();
+        if let Some((previous_block, previous)) = pending.replace((block, ticket)) {
+            while !vocoder.poll(&previous).unwrap() {}
+            vocoder.collect(previous, &mut output[previous_block * BLOCK_LENGTH..][..BLOCK_LENGTH]).unwrap();
+        }
+    }
+    let (last_block, last) = pending.unwrap();
+    vocoder.collect(last, &mut output[last_block * BLOCK_LENGTH..][..BLOCK_LENGTH]).unwrap();
+    assert!(expected[CHANGE * BLOCK_LENGTH..].iter().any(|&s| s != 0.0));
+    assert!(bitwise_equal(&output, &expected));
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+#[should_panic(expected = "not in flight")]
+fn stale_tickets_are_caught() {
+    let mut vocoder = Vocoder::new(queue(), VocoderSettings::default());
+    let ticket = vocoder.submit(&[0.0f32; BLOCK_LENGTH]).unwrap();
+    // rebuilding drops the block in flight along with the old vocoder
+    let context = vocoder.context().clone();
+    vocoder.recover_on(&context).unwrap();
+    let _ = vocoder.collect(ticket, &mut [0.0f32; BLOCK_LENGTH]);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn a_third_block_waits_for_a_slot() {
+    let mut vocoder = Vocoder::new(queue(), VocoderSettings::default());
+    let src = sine(BLOCK_LENGTH);
+    let mut dest = vec![0.0f32; BLOCK_LENGTH];
+    let first = vocoder.submit(&src).unwrap();
+    let second = vocoder.submit(&src).unwrap();
+    assert_eq!(vocoder.submit(&src), Err(VocoderError::Busy));
+    // refusing left both slots as they were
+    vocoder.collect(first, &mut dest).unwrap();
+    let third = vocoder.submit(&src).unwrap();
+    vocoder.collect(second, &mut dest).unwrap();
+    vocoder.collect(third, &mut dest).unwrap();
+}
+
+#[test]
+#[ignore = "needs a Vulkan device"]
+fn pipelined_is_one_block_late() {
+    let queue = queue();
+    let src = sine(BLOCKS * BLOCK_LENGTH);
+    let expected = process_blockwise(&mut Vocoder::new(queue.clone(), settings(0)), &src, |_, _| {});
+    let output = process_blockwise(&mut PipelinedVocoder::new(Vocoder::new(queue, settings(0))), &src, |_, _| {});
+    assert!(output[..PipelinedVocoder::LATENCY].iter().all(|&s| s == 0.0));
+    assert!(bitwise_equal(&output[PipelinedVocoder::LATENCY..], &expected[..expected.len() - PipelinedVocoder::LATENCY]));
+}